@@ -0,0 +1,190 @@
+//! Renders a single frame from a given camera pose and writes it to a PNG or PPM file.
+//! Runs entirely on the CPU, so it works without a display attached.
+use std::path::PathBuf;
+
+use ggez::{GameError, GameResult};
+use raycaster::level::Level;
+use raycaster::lighting::Torch;
+use raycaster::player::Player;
+use raycaster::renderer::{read_rgba8, write_frame, RenderSettings, Renderer};
+use raycaster::utilities::vector2::Vector2;
+
+const USAGE: &str = "Usage: raycaster-render [options] <output.png|output.ppm>
+
+Options:
+    --assets <dir>      directory holding the textures (default: assets)
+    --level <file>      level to render (default: assets/level1.ron)
+    --size <w>x<h>      resolution of the frame (default: 1920x1080)
+    --fov <degrees>     horizontal field of view (default: 45)
+    --rays-per-pixel <n>
+                        width of the column drawn by each ray (default: 2)
+    --pos <x>,<y>       player position in map cells (default: spawn point)
+    --dir <x>,<y>       view direction (default: spawn direction)
+    --pitch <px>        vertical look offset (default: 0)
+    --jump <px>         camera height offset (default: 0)
+    --time <s>          sprite animation time (default: 0)
+    --intensity <f>     torch light intensity (default: 1)";
+
+struct Options {
+    assets: PathBuf,
+    level: PathBuf,
+    output: PathBuf,
+    settings: RenderSettings,
+    pos: Option<Vector2<f32>>,
+    dir: Option<Vector2<f32>>,
+    pitch: f32,
+    jump: f32,
+    time: f32,
+    intensity: f32,
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> GameResult {
+    let options = parse_args(std::env::args().skip(1))?;
+    let settings = options.settings;
+
+    let level = Level::load_file(&options.level)?;
+    let lighting = level.lighting();
+    let Level {
+        map,
+        mut sprites,
+        spawn,
+        ..
+    } = level;
+    let pos = options
+        .pos
+        .unwrap_or_else(|| Vector2::new(spawn.pos.0, spawn.pos.1));
+    if pos.x < 0.0
+        || pos.y < 0.0
+        || pos.x >= map.size.0 as f32
+        || pos.y >= map.size.1 as f32
+        || map.solid[pos.x as usize + pos.y as usize * map.size.0]
+    {
+        return Err(usage_error(
+            "position must be inside an empty cell of the map",
+        ));
+    }
+    let torch = Torch::with_intensity(options.intensity);
+
+    let mut dir_norm = options
+        .dir
+        .unwrap_or_else(|| Vector2::new(spawn.dir.0, spawn.dir.1));
+    dir_norm.normalize();
+    let player = Player::new(
+        pos,
+        dir_norm,
+        settings.plane(dir_norm),
+        settings.planedist(),
+        options.pitch,
+        options.jump,
+    );
+
+    sprites
+        .iter_mut()
+        .for_each(|sprite| sprite.update(options.time));
+
+    let mut renderer = Renderer::new(
+        settings,
+        read_rgba8(&options.assets.join("wall128.png"))?,
+        read_rgba8(&options.assets.join("sprite128.png"))?,
+    );
+    renderer.render(&map, &player, &mut sprites, &lighting, &torch);
+
+    write_frame(
+        &options.output,
+        renderer.frame(),
+        settings.width,
+        settings.height,
+    )
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> GameResult<Options> {
+    let mut options = Options {
+        assets: PathBuf::from("assets"),
+        level: PathBuf::from("assets/level1.ron"),
+        output: PathBuf::new(),
+        settings: RenderSettings::default(),
+        pos: None,
+        dir: None,
+        pitch: 0.0,
+        jump: 0.0,
+        time: 0.0,
+        intensity: 1.0,
+    };
+    let mut output = None;
+
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            output = Some(PathBuf::from(arg));
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| usage_error(&format!("missing value for {}", arg)))?;
+        match arg.as_str() {
+            "--assets" => options.assets = PathBuf::from(value),
+            "--level" => options.level = PathBuf::from(value),
+            "--size" => {
+                let (w, h) = parse_pair(&value, 'x')?;
+                if w < 1.0 || h < 1.0 {
+                    return Err(usage_error("size must be positive"));
+                }
+                options.settings.width = w as usize;
+                options.settings.height = h as usize;
+            }
+            "--fov" => {
+                options.settings.fov = parse_number(&value)?;
+                if !(options.settings.fov > 0.0 && options.settings.fov < 180.0) {
+                    return Err(usage_error("fov must be between 0 and 180 degrees"));
+                }
+            }
+            "--rays-per-pixel" => {
+                options.settings.rays_per_pixel = parse_number(&value)? as usize;
+                if options.settings.rays_per_pixel == 0 {
+                    return Err(usage_error("rays per pixel must be at least 1"));
+                }
+            }
+            "--pos" => {
+                options.pos = Some(parse_pair(&value, ',').map(|(x, y)| Vector2::new(x, y))?)
+            }
+            "--dir" => {
+                options.dir = Some(parse_pair(&value, ',').map(|(x, y)| Vector2::new(x, y))?)
+            }
+            "--pitch" => options.pitch = parse_number(&value)?,
+            "--jump" => options.jump = parse_number(&value)?,
+            "--time" => options.time = parse_number(&value)?,
+            "--intensity" => options.intensity = parse_number(&value)?,
+            _ => return Err(usage_error(&format!("unknown option {}", arg))),
+        }
+    }
+
+    if options.dir.is_some_and(|dir| dir.magnitude() == 0.0) {
+        return Err(usage_error("direction can't be zero"));
+    }
+    options.output = output.ok_or_else(|| usage_error("missing output file"))?;
+    Ok(options)
+}
+
+fn parse_pair(value: &str, separator: char) -> GameResult<(f32, f32)> {
+    let (a, b) = value
+        .split_once(separator)
+        .ok_or_else(|| usage_error(&format!("expected two values separated by '{}'", separator)))?;
+    Ok((parse_number(a)?, parse_number(b)?))
+}
+
+fn parse_number(value: &str) -> GameResult<f32> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| usage_error(&format!("invalid number '{}'", value)))
+}
+
+fn usage_error(message: &str) -> GameError {
+    GameError::ConfigError(format!("{}\n\n{}", message, USAGE))
+}
@@ -0,0 +1,165 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use ggez::{filesystem, Context, GameError, GameResult};
+use serde::Deserialize;
+
+use crate::level::Exit;
+
+/// Seconds it takes to fade to black, and as many to fade back in.
+const FADE_TIME: f32 = 0.5;
+
+/// Levels played one after the other, listed in a RON file such as `/campaign.ron`:
+/// `(levels: ["level1.ron", "level2.ron"])`, with paths relative to the campaign file.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct Campaign {
+    pub levels: Vec<PathBuf>,
+}
+
+impl Campaign {
+    /// Loads a campaign file through the ggez filesystem.
+    pub fn load(ctx: &mut Context, path: &Path) -> GameResult<Self> {
+        let mut text = String::new();
+        filesystem::open(ctx, path)?.read_to_string(&mut text)?;
+        Self::parse(&text, path)
+    }
+
+    /// Reads a campaign file, resolving its levels relative to `path`.
+    pub fn parse(text: &str, path: &Path) -> GameResult<Self> {
+        let error = |e: String| GameError::ResourceLoadError(format!("{}: {}", path.display(), e));
+        let campaign: Self = ron::from_str(text).map_err(|e| error(e.to_string()))?;
+        if campaign.levels.is_empty() {
+            return Err(error("campaign has no levels".to_string()));
+        }
+        let dir = path.parent().unwrap_or_else(|| Path::new("/"));
+        Ok(Self {
+            levels: campaign
+                .levels
+                .iter()
+                .map(|level| dir.join(level))
+                .collect(),
+        })
+    }
+
+    /// Campaign made of a single level, which its exits lead back to unless they name
+    /// another one.
+    pub fn single(level: &Path) -> Self {
+        Self {
+            levels: vec![level.to_path_buf()],
+        }
+    }
+
+    pub fn first(&self) -> &Path {
+        &self.levels[0]
+    }
+
+    /// Level that an exit of `current` leads to: the one it names, relative to `current`, else
+    /// the level after `current`. The campaign starts over after its last level.
+    pub fn destination(&self, current: &Path, exit: &Exit) -> PathBuf {
+        if let Some(to) = &exit.to {
+            return current.parent().unwrap_or_else(|| Path::new("/")).join(to);
+        }
+        let next = self
+            .levels
+            .iter()
+            .position(|level| level == current)
+            .map_or(0, |i| (i + 1) % self.levels.len());
+        self.levels[next].clone()
+    }
+}
+
+/// Fade to black while the next level loads, then back to the game.
+#[derive(Debug)]
+pub struct Transition {
+    /// Level to load once the screen is black, taken when it is loaded.
+    next: Option<PathBuf>,
+    /// From 0, the game in full view, to 1, a black screen.
+    pub fade: f32,
+}
+
+impl Transition {
+    pub fn new(next: PathBuf) -> Self {
+        Self {
+            next: Some(next),
+            fade: 0.0,
+        }
+    }
+
+    /// Whether the screen is black and the next level is still to be loaded.
+    pub fn loading(&self) -> bool {
+        self.next.is_some() && self.fade >= 1.0
+    }
+
+    /// Advances the fade by `dt` seconds and returns the level to load, once the screen has
+    /// been drawn black for a frame.
+    pub fn update(&mut self, dt: f32) -> Option<PathBuf> {
+        if self.loading() {
+            return self.next.take();
+        }
+        if self.next.is_some() {
+            self.fade = (self.fade + dt / FADE_TIME).min(1.0);
+        } else {
+            self.fade = (self.fade - dt / FADE_TIME).max(0.0);
+        }
+        None
+    }
+
+    pub fn finished(&self) -> bool {
+        self.next.is_none() && self.fade <= 0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::Level;
+
+    fn exit(to: Option<&str>) -> Exit {
+        Exit {
+            cell: 0,
+            to: to.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn exits_lead_to_the_next_level() {
+        let campaign = Campaign::parse(
+            r#"(levels: ["level1.ron", "castle/level2.ron"])"#,
+            Path::new("/campaign.ron"),
+        )
+        .unwrap();
+        let (first, second) = (Path::new("/level1.ron"), Path::new("/castle/level2.ron"));
+
+        assert_eq!(campaign.first(), first);
+        assert_eq!(campaign.destination(first, &exit(None)), second);
+        assert_eq!(campaign.destination(second, &exit(None)), first);
+        assert_eq!(
+            campaign.destination(second, &exit(Some("secret.ron"))),
+            Path::new("/castle/secret.ron")
+        );
+    }
+
+    #[test]
+    fn every_level_of_the_campaign_loads() {
+        let path = Path::new("assets/campaign.ron");
+        let campaign = Campaign::parse(&std::fs::read_to_string(path).unwrap(), path).unwrap();
+
+        for level in &campaign.levels {
+            let level = Level::load_file(level).unwrap();
+            assert!(!level.exits.is_empty());
+        }
+    }
+
+    #[test]
+    fn loads_the_next_level_on_a_black_screen() {
+        let mut transition = Transition::new(PathBuf::from("/level2.ron"));
+
+        assert_eq!(transition.update(FADE_TIME * 0.5), None);
+        assert_eq!(transition.update(FADE_TIME), None);
+        assert!(transition.loading());
+        assert_eq!(transition.update(0.0), Some(PathBuf::from("/level2.ron")));
+        assert!(!transition.finished());
+        transition.update(FADE_TIME);
+        assert!(transition.finished());
+    }
+}
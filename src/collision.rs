@@ -0,0 +1,226 @@
+//! Collision of round bodies, such as the player, with the solid cells of the map and the solid
+//! sprites standing on it.
+use crate::map::Map;
+use crate::sprite::Sprite;
+use crate::utilities::vector2::Vector2;
+
+/// Radius of the circle the player takes up, in cells.
+pub const PLAYER_RADIUS: f32 = 0.3125;
+/// Radius of the circle a solid sprite, such as an armor, takes up.
+pub const SPRITE_RADIUS: f32 = 0.3;
+/// Times the circle is pushed out of what it overlaps after each step, since getting out of one
+/// obstacle can push it into another.
+const PASSES: usize = 4;
+
+/// Moves a circle of `radius` centred on `pos` by `delta` and returns where its centre ends up.
+///
+/// The circle stops against solid cells and solid sprites and keeps the part of `delta` that
+/// runs along them, sliding along walls and around corners. Long moves are split into steps of
+/// half the radius so that the circle can't go through a wall in one step.
+pub fn move_with_collision(
+    map: &Map,
+    sprites: &[Sprite],
+    pos: Vector2<f32>,
+    delta: Vector2<f32>,
+    radius: f32,
+) -> Vector2<f32> {
+    let steps = (delta.magnitude() / (radius * 0.5)).ceil().max(1.0);
+    let step = delta * (1.0 / steps);
+    let mut pos = pos;
+    for _ in 0..steps as usize {
+        pos += step;
+        // Getting out of the deepest overlap first keeps the circle from catching on the
+        // corners where two cells of a straight wall meet.
+        for _ in 0..PASSES {
+            match cell_overlaps(map, pos, radius)
+                .chain(sprite_overlaps(sprites, pos, radius))
+                .max_by(|a, b| a.0.total_cmp(&b.0))
+            {
+                Some((_, out)) => pos = out,
+                None => break,
+            }
+        }
+    }
+    pos
+}
+
+/// Whether the cell at `(x, y)` stops the player. Cells off the map do.
+fn solid(map: &Map, x: isize, y: isize) -> bool {
+    let (w, h) = (map.size.0 as isize, map.size.1 as isize);
+    !(0..w).contains(&x) || !(0..h).contains(&y) || map.solid[(x + y * w) as usize]
+}
+
+/// Whether a circle of `radius` centred on `pos` overlaps the cell at `(x, y)`. A circle of
+/// radius 0 does when its centre is in the cell.
+pub fn overlaps_cell(pos: Vector2<f32>, radius: f32, (x, y): (usize, usize)) -> bool {
+    let (left, top) = (x as f32, y as f32);
+    let closest = Vector2::new(pos.x.clamp(left, left + 1.0), pos.y.clamp(top, top + 1.0));
+    closest == pos || (pos - closest).magnitude() < radius
+}
+
+/// How deep the circle sinks into each solid cell it overlaps, with where the shortest way out
+/// of the cell would put it.
+fn cell_overlaps(
+    map: &Map,
+    pos: Vector2<f32>,
+    radius: f32,
+) -> impl Iterator<Item = (f32, Vector2<f32>)> + '_ {
+    let (x0, x1) = (
+        (pos.x - radius).floor() as isize,
+        (pos.x + radius).floor() as isize,
+    );
+    let (y0, y1) = (
+        (pos.y - radius).floor() as isize,
+        (pos.y + radius).floor() as isize,
+    );
+    (y0..=y1)
+        .flat_map(move |y| (x0..=x1).map(move |x| (x, y)))
+        .filter(|&(x, y)| solid(map, x, y))
+        .filter_map(move |(x, y)| {
+            let (left, top) = (x as f32, y as f32);
+            let closest = Vector2::new(pos.x.clamp(left, left + 1.0), pos.y.clamp(top, top + 1.0));
+            let away = pos - closest;
+            let distance = away.magnitude();
+            if distance >= radius {
+                None
+            } else if distance > 0.0 {
+                Some((radius - distance, closest + away * (radius / distance)))
+            } else {
+                // The centre is inside the cell, leave through the nearest side.
+                [
+                    (pos.x - left, Vector2::new(left - radius, pos.y)),
+                    (left + 1.0 - pos.x, Vector2::new(left + 1.0 + radius, pos.y)),
+                    (pos.y - top, Vector2::new(pos.x, top - radius)),
+                    (top + 1.0 - pos.y, Vector2::new(pos.x, top + 1.0 + radius)),
+                ]
+                .into_iter()
+                .min_by(|a, b| a.0.total_cmp(&b.0))
+                .map(|(inside, out)| (radius + inside, out))
+            }
+        })
+}
+
+/// How deep the circle sinks into each solid sprite it overlaps, with where it would be once
+/// pushed out of it.
+fn sprite_overlaps(
+    sprites: &[Sprite],
+    pos: Vector2<f32>,
+    radius: f32,
+) -> impl Iterator<Item = (f32, Vector2<f32>)> + '_ {
+    let reach = radius + SPRITE_RADIUS;
+    sprites
+        .iter()
+        .filter(|sprite| sprite.solid())
+        .filter_map(move |sprite| {
+            let away = pos - sprite.pos;
+            let distance = away.magnitude();
+            if distance >= reach {
+                None
+            } else if distance > 0.0 {
+                Some((reach - distance, sprite.pos + away * (reach / distance)))
+            } else {
+                Some((reach, sprite.pos + Vector2::new(reach, 0.0)))
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sprite::SpriteType;
+
+    const ROOM: &str = "
+        #######
+        #.....#
+        #.P...#
+        #...#.#
+        #.....#
+        #######
+    ";
+
+    fn room() -> (Map, Vector2<f32>) {
+        let (map, spawn) = Map::from_ascii(ROOM).unwrap();
+        (map, spawn.unwrap())
+    }
+
+    fn close(a: Vector2<f32>, b: (f32, f32)) -> bool {
+        (a.x - b.0).abs() < 1e-4 && (a.y - b.1).abs() < 1e-4
+    }
+
+    /// Distance from `pos` to the nearest solid cell of `map`.
+    fn clearance(map: &Map, pos: Vector2<f32>) -> f32 {
+        let mut nearest = f32::MAX;
+        for y in 0..map.size.1 as isize {
+            for x in 0..map.size.0 as isize {
+                if solid(map, x, y) {
+                    let closest = Vector2::new(
+                        pos.x.clamp(x as f32, x as f32 + 1.0),
+                        pos.y.clamp(y as f32, y as f32 + 1.0),
+                    );
+                    nearest = nearest.min((pos - closest).magnitude());
+                }
+            }
+        }
+        nearest
+    }
+
+    #[test]
+    fn stops_against_walls() {
+        let (map, spawn) = room();
+        let pos = move_with_collision(&map, &[], spawn, Vector2::new(-2.0, 0.0), 0.25);
+
+        assert!(close(pos, (1.25, 2.5)), "{:?}", pos);
+    }
+
+    #[test]
+    fn slides_along_walls() {
+        let (map, spawn) = room();
+        let pos = move_with_collision(&map, &[], spawn, Vector2::new(-2.0, -0.5), 0.25);
+
+        assert!(close(pos, (1.25, 2.0)), "{:?}", pos);
+    }
+
+    #[test]
+    fn slides_around_corners() {
+        let (map, _) = room();
+        // Walking down past the pillar at (4, 3), grazing its top right corner.
+        let start = Vector2::new(5.1, 1.5);
+        let pos = move_with_collision(&map, &[], start, Vector2::new(0.0, 2.0), 0.25);
+
+        assert!((pos.x - 5.25).abs() < 1e-4 && pos.y > 3.3, "{:?}", pos);
+        assert!(clearance(&map, pos) >= 0.25 - 1e-4);
+    }
+
+    #[test]
+    fn never_tunnels_through_walls() {
+        let (map, spawn) = room();
+        let pos = move_with_collision(&map, &[], spawn, Vector2::new(30.0, 0.0), 0.25);
+
+        assert!(close(pos, (5.75, 2.5)), "{:?}", pos);
+    }
+
+    #[test]
+    fn keeps_clear_of_walls_whichever_way_it_moves() {
+        let (map, mut pos) = room();
+        for i in 0..360 {
+            let angle = (i as f32 * 37.0).to_radians();
+            let delta = Vector2::new(angle.cos(), angle.sin()) * 0.4;
+            pos = move_with_collision(&map, &[], pos, delta, PLAYER_RADIUS);
+
+            assert!(clearance(&map, pos) >= PLAYER_RADIUS - 1e-4, "{:?}", pos);
+        }
+    }
+
+    #[test]
+    fn bumps_into_solid_sprites_only() {
+        let (map, spawn) = room();
+        let delta = Vector2::new(2.0, 0.0);
+        let armor = [Sprite::new(SpriteType::Armor, Vector2::new(4.5, 2.5))];
+        let bat = [Sprite::new(SpriteType::Bat, Vector2::new(4.5, 2.5))];
+
+        let pos = move_with_collision(&map, &armor, spawn, delta, 0.25);
+        assert!(close(pos, (4.5 - 0.25 - SPRITE_RADIUS, 2.5)), "{:?}", pos);
+        let pos = move_with_collision(&map, &bat, spawn, delta, 0.25);
+        assert!(close(pos, (4.5, 2.5)), "{:?}", pos);
+    }
+}
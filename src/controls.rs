@@ -0,0 +1,377 @@
+//! What the player does each frame, read from the mouse, keyboard and gamepads or played back
+//! from a demo.
+//!
+//! Keys are bound to actions by a [`Keymap`], loaded from a file such as `/keymap.ron`.
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::Path;
+
+use ggez::event::{Axis, Button, KeyCode};
+use ggez::input::gamepad;
+use ggez::input::keyboard::is_key_pressed;
+use ggez::{filesystem, graphics, Context, GameError, GameResult};
+use serde::{Deserialize, Serialize};
+
+use crate::utilities::input::{get_delta, mouse_grabbed_and_hidden, set_mouse_location};
+use crate::utilities::vector2::Vector2;
+
+/// What a key does. The editor's brushes keep their own keys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    StrafeLeft,
+    StrafeRight,
+    /// Runs faster while held.
+    Sprint,
+    /// Opens the door in front of the player.
+    Use,
+    /// Ducks while held.
+    Crouch,
+    /// Jumps off the floor.
+    Jump,
+    ToggleLighting,
+    ToggleSmoothLighting,
+    ToggleEditor,
+    QuickSave,
+    QuickLoad,
+    Quit,
+}
+
+/// Keys bound to each action.
+///
+/// In a keymap file it reads like `{ MoveForward: [Z, Up], StrafeLeft: [Q] }`, with keys named
+/// after [`KeyCode`]. An action that is left out keeps its default keys, an action that is
+/// given gets only the listed ones. Gamepads aren't read from the file, they keep the
+/// [`GamepadSettings`] they come with.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keymap {
+    bindings: BTreeMap<Action, Vec<KeyCode>>,
+    pub gamepad: GamepadSettings,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self {
+            bindings: BTreeMap::from([
+                (Action::MoveForward, vec![KeyCode::W]),
+                (Action::MoveBackward, vec![KeyCode::S]),
+                (Action::StrafeLeft, vec![KeyCode::A]),
+                (Action::StrafeRight, vec![KeyCode::D]),
+                (Action::Sprint, vec![KeyCode::LShift]),
+                (Action::Use, vec![KeyCode::Space]),
+                (Action::Crouch, vec![KeyCode::LControl]),
+                (Action::Jump, vec![KeyCode::Q]),
+                (Action::ToggleLighting, vec![KeyCode::L]),
+                (Action::ToggleSmoothLighting, vec![KeyCode::K]),
+                (Action::ToggleEditor, vec![KeyCode::Tab]),
+                (Action::QuickSave, vec![KeyCode::F5]),
+                (Action::QuickLoad, vec![KeyCode::F9]),
+                (Action::Quit, vec![KeyCode::Escape]),
+            ]),
+            gamepad: GamepadSettings::default(),
+        }
+    }
+}
+
+impl Keymap {
+    /// Loads a keymap file through the ggez filesystem, or the default keymap if there is none.
+    pub fn load(ctx: &mut Context, path: &Path) -> GameResult<Self> {
+        if !filesystem::exists(ctx, path) {
+            return Ok(Self::default());
+        }
+        let mut text = String::new();
+        filesystem::open(ctx, path)?.read_to_string(&mut text)?;
+        Self::parse(&text, path)
+    }
+
+    /// Reads the bindings of a keymap file on top of the default ones.
+    ///
+    /// A key the file binds is taken away from the default action it was bound to, so that e.g.
+    /// an AZERTY keymap can move `StrafeLeft` to `Q`. Only keys bound twice within the file are
+    /// rejected.
+    pub fn parse(text: &str, path: &Path) -> GameResult<Self> {
+        let error = |e: String| GameError::ResourceLoadError(format!("{}: {}", path.display(), e));
+        let bindings: BTreeMap<Action, Vec<KeyCode>> =
+            ron::from_str(text).map_err(|e| error(e.to_string()))?;
+
+        let mut actions = BTreeMap::new();
+        for (&action, keys) in &bindings {
+            for &key in keys {
+                if let Some(other) = actions.insert(key, action) {
+                    return Err(error(format!(
+                        "{:?} is bound to both {:?} and {:?}",
+                        key, other, action
+                    )));
+                }
+            }
+        }
+
+        let mut keymap = Self::default();
+        for keys in keymap.bindings.values_mut() {
+            keys.retain(|key| !actions.contains_key(key));
+        }
+        keymap.bindings.extend(bindings);
+        Ok(keymap)
+    }
+
+    /// Action bound to `key`, if any.
+    pub fn action(&self, key: KeyCode) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|(_, keys)| keys.contains(&key))
+            .map(|(&action, _)| action)
+    }
+
+    /// Whether a key bound to `action` is held down.
+    pub fn pressed(&self, ctx: &Context, action: Action) -> bool {
+        self.bindings
+            .get(&action)
+            .into_iter()
+            .flatten()
+            .any(|&key| is_key_pressed(ctx, key))
+    }
+}
+
+/// How the sticks and buttons of a gamepad steer the player.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GamepadSettings {
+    /// Share of a stick's travel around its centre that is ignored, so that a stick which
+    /// doesn't rest exactly at zero leaves the player still.
+    pub dead_zone: f32,
+    /// Power the tilt of the left stick is raised to before walking.
+    pub move_curve: f32,
+    /// Power the tilt of the right stick is raised to before looking. Above 1, small tilts aim
+    /// finely and a full tilt still turns quickly.
+    pub look_curve: f32,
+    /// Pixels of mouse motion a fully tilted right stick stands for each second, sideways and
+    /// up and down.
+    pub look_speed: (f32, f32),
+    /// Buttons held down for the actions read every frame.
+    pub buttons: Vec<(Button, Action)>,
+}
+
+impl Default for GamepadSettings {
+    fn default() -> Self {
+        Self {
+            dead_zone: 0.2,
+            move_curve: 1.0,
+            look_curve: 2.0,
+            look_speed: (1800.0, 600.0),
+            buttons: vec![
+                (Button::LeftThumb, Action::Sprint),
+                (Button::South, Action::Jump),
+                (Button::West, Action::Use),
+                (Button::RightTrigger2, Action::Use),
+                (Button::East, Action::Crouch),
+                (Button::LeftTrigger2, Action::Crouch),
+            ],
+        }
+    }
+}
+
+impl GamepadSettings {
+    /// Tilt of a stick with the dead zone cut out, rescaled to reach 1 again at full tilt and
+    /// raised to `curve`, in the direction the stick points.
+    pub fn shape(&self, (x, y): (f32, f32), curve: f32) -> (f32, f32) {
+        let tilt = (x * x + y * y).sqrt();
+        if tilt <= self.dead_zone {
+            return (0.0, 0.0);
+        }
+        let shaped = ((tilt.min(1.0) - self.dead_zone) / (1.0 - self.dead_zone)).powf(curve);
+        (x / tilt * shaped, y / tilt * shaped)
+    }
+
+    /// Adds the sticks and buttons of every connected gamepad to `input`: the left stick walks,
+    /// the right one looks around like the mouse does.
+    fn read(&self, ctx: &Context, input: &mut FrameInput) {
+        for (_, pad) in gamepad::gamepads(ctx) {
+            let (x, y) = self.shape(
+                (pad.value(Axis::LeftStickX), pad.value(Axis::LeftStickY)),
+                self.move_curve,
+            );
+            input.walk.0 += x;
+            input.walk.1 += y;
+
+            let (x, y) = self.shape(
+                (pad.value(Axis::RightStickX), pad.value(Axis::RightStickY)),
+                self.look_curve,
+            );
+            // Sticks point up with positive values, the mouse moves down with them.
+            input.mouse.0 += x * self.look_speed.0 * input.dt;
+            input.mouse.1 -= y * self.look_speed.1 * input.dt;
+
+            for &(button, action) in &self.buttons {
+                if pad.is_pressed(button) {
+                    input.hold(action);
+                }
+            }
+        }
+    }
+}
+
+/// Input of one frame, everything the game logic reads from the outside world.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FrameInput {
+    /// Seconds since the previous frame.
+    pub dt: f32,
+    /// Motion of the mouse in pixels, turning the player and tilting their view.
+    pub mouse: (f32, f32),
+    pub forward: bool,
+    pub backward: bool,
+    pub left: bool,
+    pub right: bool,
+    /// Walking from an analog stick, strafing right and moving forward, each from -1 to 1.
+    #[serde(default)]
+    pub walk: (f32, f32),
+    #[serde(default)]
+    pub sprint: bool,
+    /// Opens the door in front of the player.
+    pub open: bool,
+    pub crouch: bool,
+    #[serde(default)]
+    pub jump: bool,
+}
+
+impl FrameInput {
+    /// A frame where the player does nothing.
+    pub fn idle(dt: f32) -> Self {
+        Self {
+            dt,
+            ..Default::default()
+        }
+    }
+
+    /// Reads the keys bound in `keymap`, the motion of the mouse and the gamepads, then grabs
+    /// the mouse back to the centre of the window.
+    pub fn read(ctx: &mut Context, keymap: &Keymap) -> Self {
+        let (w, h) = graphics::drawable_size(ctx);
+        mouse_grabbed_and_hidden(ctx, false, true).unwrap();
+
+        let mut delta = get_delta(ctx);
+        let center = Vector2::new(w * 0.5, h * 0.5);
+        set_mouse_location(ctx, center).unwrap();
+
+        // The cursor is recentered in logical pixels but its motion is reported in physical
+        // ones, so on scaled displays every delta is off by the centre times the extra scale.
+        let recenter = center * (graphics::window(ctx).scale_factor() as f32 - 1.0);
+        if delta.x != 0.0 {
+            delta.x -= recenter.x;
+        }
+        if delta.y != 0.0 {
+            delta.y -= recenter.y;
+        }
+
+        let mut input = Self {
+            dt: ggez::timer::delta(ctx).as_secs_f32(),
+            mouse: (delta.x, delta.y),
+            ..Default::default()
+        };
+        for action in Self::HELD {
+            if keymap.pressed(ctx, action) {
+                input.hold(action);
+            }
+        }
+        keymap.gamepad.read(ctx, &mut input);
+        input
+    }
+
+    /// Actions that last as long as their key or button is held down.
+    const HELD: [Action; 8] = [
+        Action::MoveForward,
+        Action::MoveBackward,
+        Action::StrafeLeft,
+        Action::StrafeRight,
+        Action::Sprint,
+        Action::Use,
+        Action::Crouch,
+        Action::Jump,
+    ];
+
+    /// Marks `action` as held down this frame. Actions done once per press are left to the
+    /// key events.
+    fn hold(&mut self, action: Action) {
+        match action {
+            Action::MoveForward => self.forward = true,
+            Action::MoveBackward => self.backward = true,
+            Action::StrafeLeft => self.left = true,
+            Action::StrafeRight => self.right = true,
+            Action::Sprint => self.sprint = true,
+            Action::Use => self.open = true,
+            Action::Crouch => self.crouch = true,
+            Action::Jump => self.jump = true,
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebinds_actions_on_top_of_the_defaults() {
+        let keymap = Keymap::parse(
+            "{ MoveForward: [Z, Up], StrafeLeft: [Q], Jump: [A] }",
+            Path::new("/keymap.ron"),
+        )
+        .unwrap();
+
+        assert_eq!(keymap.action(KeyCode::Z), Some(Action::MoveForward));
+        assert_eq!(keymap.action(KeyCode::Up), Some(Action::MoveForward));
+        assert_eq!(keymap.action(KeyCode::Q), Some(Action::StrafeLeft));
+        assert_eq!(keymap.action(KeyCode::W), None);
+        assert_eq!(keymap.action(KeyCode::D), Some(Action::StrafeRight));
+    }
+
+    #[test]
+    fn rejects_keys_bound_twice() {
+        match Keymap::parse("{ StrafeLeft: [Q], Jump: [Q] }", Path::new("/keymap.ron")) {
+            Err(GameError::ResourceLoadError(e)) => {
+                assert_eq!(e, "/keymap.ron: Q is bound to both StrafeLeft and Jump")
+            }
+            _ => panic!("key bound twice was accepted"),
+        }
+    }
+
+    #[test]
+    fn takes_keys_away_from_the_defaults() {
+        let azerty = "{ MoveForward: [Z], StrafeLeft: [Q], Jump: [A] }";
+        let keymap = Keymap::parse(azerty, Path::new("/keymap.ron")).unwrap();
+
+        assert_eq!(keymap.action(KeyCode::Z), Some(Action::MoveForward));
+        assert_eq!(keymap.action(KeyCode::Q), Some(Action::StrafeLeft));
+        assert_eq!(keymap.action(KeyCode::A), Some(Action::Jump));
+        assert_eq!(keymap.action(KeyCode::W), None);
+        assert_eq!(keymap.action(KeyCode::S), Some(Action::MoveBackward));
+    }
+
+    #[test]
+    fn sticks_ignore_the_dead_zone() {
+        let settings = GamepadSettings::default();
+
+        assert_eq!(settings.shape((0.1, -0.15), 1.0), (0.0, 0.0));
+        assert_eq!(settings.shape((0.0, 1.0), 1.0), (0.0, 1.0));
+        let (x, y) = settings.shape((-0.6, 0.0), 1.0);
+        assert!((x + 0.5).abs() < 1e-6 && y == 0.0);
+        let (x, y) = settings.shape((0.6, 0.8), 1.0);
+        assert!((x - 0.6).abs() < 1e-6 && (y - 0.8).abs() < 1e-6);
+    }
+
+    #[test]
+    fn look_curve_slows_small_tilts() {
+        let settings = GamepadSettings::default();
+        let (x, _) = settings.shape((0.6, 0.0), settings.look_curve);
+
+        assert!((x - 0.25).abs() < 1e-6);
+        assert_eq!(settings.shape((1.0, 0.0), settings.look_curve), (1.0, 0.0));
+    }
+
+    #[test]
+    fn keymap_file_holds_the_defaults() {
+        let path = Path::new("assets/keymap.ron");
+        let keymap = Keymap::parse(&std::fs::read_to_string(path).unwrap(), path).unwrap();
+
+        assert_eq!(keymap, Keymap::default());
+    }
+}
@@ -0,0 +1,150 @@
+//! Demos: the input of every frame of a game, recorded to a file and played back instead of the
+//! mouse and keyboard to replay the game.
+use std::path::{Path, PathBuf};
+
+use ggez::{GameError, GameResult};
+use serde::{Deserialize, Serialize};
+
+use crate::controls::FrameInput;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Demo {
+    /// Level the demo starts on, from its spawn, e.g. `/level1.ron`.
+    pub level: PathBuf,
+    /// Seed of the random numbers drawn during the game.
+    pub seed: u64,
+    pub frames: Vec<FrameInput>,
+}
+
+impl Demo {
+    pub fn new(level: &Path, seed: u64) -> Self {
+        Self {
+            level: level.to_path_buf(),
+            seed,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn load_file(path: &Path) -> GameResult<Self> {
+        let text = std::fs::read_to_string(path)?;
+        ron::from_str(&text)
+            .map_err(|e| GameError::ResourceLoadError(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Writes the demo with a frame per line.
+    pub fn save_file(&self, path: &Path) -> GameResult {
+        let config = ron::ser::PrettyConfig::default().depth_limit(2);
+        let text = ron::ser::to_string_pretty(self, config)
+            .map_err(|e| GameError::CustomError(format!("{}: {}", path.display(), e)))?;
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+/// Where the input of the game goes to or comes from while a demo runs.
+pub enum DemoMode {
+    /// Every frame is added to the demo, written to `path` when the game quits.
+    Recording { demo: Demo, path: PathBuf },
+    /// Frames come from the demo, `next` being the one to play.
+    Playing { demo: Demo, next: usize },
+}
+
+impl DemoMode {
+    /// Input of the next frame when playing, `None` once the demo is over.
+    pub fn next_frame(&mut self) -> Option<FrameInput> {
+        match self {
+            Self::Recording { .. } => None,
+            Self::Playing { demo, next } => {
+                let frame = demo.frames.get(*next).copied();
+                *next += 1;
+                frame
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    use crate::level::Level;
+    use crate::lighting::Torch;
+    use crate::player::Player;
+    use crate::renderer::RenderSettings;
+    use crate::savegame::{RngState, SaveGame};
+    use crate::timestep::{Timestep, TICK};
+    use crate::utilities::vector2::Vector2;
+    use crate::{simulate, GameRng};
+
+    fn demo_level() -> PathBuf {
+        PathBuf::from("/level1.ron")
+    }
+
+    /// Plays `frames` with random numbers drawn from `seed` from the spawn of the first level,
+    /// the way the game does, and returns the state of the game at the end.
+    fn play(frames: &[FrameInput], seed: u64) -> SaveGame {
+        let mut level = Level::load_file(Path::new("assets/level1.ron")).unwrap();
+        let settings = RenderSettings::default();
+        let dir = Vector2::new(level.spawn.dir.0, level.spawn.dir.1);
+        let mut player = Player::new(
+            Vector2::new(level.spawn.pos.0, level.spawn.pos.1),
+            dir,
+            settings.plane(dir),
+            settings.planedist(),
+            0.0,
+            0.0,
+        );
+        let mut rng = GameRng::seed_from_u64(seed);
+        let mut torch = Torch::new(&mut rng);
+        let mut timestep = Timestep::default();
+        let mut time = 0.0;
+        for input in frames {
+            player.look(input.mouse);
+            for _ in 0..timestep.advance(input.dt) {
+                time += TICK;
+                simulate(
+                    &mut level,
+                    &mut player,
+                    &mut torch,
+                    &mut rng,
+                    time,
+                    Some(input),
+                );
+            }
+        }
+        SaveGame::capture(
+            &demo_level(),
+            &level,
+            &player,
+            time,
+            &torch,
+            RngState::capture(seed, &rng),
+        )
+    }
+
+    #[test]
+    fn replays_the_recorded_path() {
+        let mut demo = Demo::new(&demo_level(), 7);
+        for i in 0..240 {
+            demo.frames.push(FrameInput {
+                dt: 1.0 / 60.0 + (i % 7) as f32 * 0.001,
+                mouse: (if i < 30 { 1.3 } else { 0.0 }, -0.4),
+                forward: i < 120,
+                left: (60..90).contains(&i),
+                open: i == 100,
+                ..FrameInput::idle(0.0)
+            });
+        }
+        let recorded = play(&demo.frames, demo.seed);
+
+        let path = std::env::temp_dir().join(format!("raycaster-{}.demo", std::process::id()));
+        demo.save_file(&path).unwrap();
+        let loaded = Demo::load_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, demo);
+        assert_eq!(play(&loaded.frames, loaded.seed), recorded);
+        assert_ne!(recorded.player.pos, (8.5, 12.5));
+    }
+}
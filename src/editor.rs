@@ -0,0 +1,440 @@
+//! Editor mode, toggled in game with Tab: a top-down view of the level painted with the mouse,
+//! while the first-person view behind it keeps rendering every change.
+use ggez::event::KeyCode;
+use ggez::graphics::{self, Color, DrawMode, DrawParam, MeshBuilder, Rect};
+use ggez::{Context, GameResult};
+
+use crate::collision::{overlaps_cell, PLAYER_RADIUS};
+use crate::level::Level;
+use crate::minimap::Minimap;
+use crate::palette::{Palette, Tile};
+use crate::player::Player;
+use crate::sprite::{Sprite, SpriteType};
+use crate::utilities::vector2::Vector2;
+
+/// Space left around the grid, in pixels.
+const MARGIN: f32 = 48.0;
+/// Largest size of a cell, in pixels.
+const MAX_CELL_SIZE: f32 = 48.0;
+const DIGITS: [KeyCode; 9] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+];
+const HELP: &str = "1-9 walls, F floors, R door, T torch, G sprites, P spawn, \
+                    right click erases, Ctrl+S saves, Tab leaves";
+
+/// What the left mouse button paints.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Brush {
+    Wall(usize),
+    Floor(usize),
+    Door,
+    Torch,
+    Sprite(SpriteType),
+    Spawn,
+}
+
+pub struct Editor {
+    pub active: bool,
+    pub brush: Brush,
+    /// Outcome of the last action worth telling, shown under the brush.
+    pub status: String,
+}
+
+impl Default for Editor {
+    fn default() -> Self {
+        Self {
+            active: false,
+            brush: Brush::Wall(2),
+            status: String::new(),
+        }
+    }
+}
+
+impl Editor {
+    /// Picks the brush bound to `keycode`, returns whether there was one.
+    ///
+    /// Wall textures must be in the palette so that the level can be saved.
+    pub fn select_brush(&mut self, keycode: KeyCode, palette: &Palette) -> bool {
+        self.brush = match keycode {
+            KeyCode::F => {
+                let mut floors: Vec<usize> = palette.floors.values().copied().collect();
+                floors.sort_unstable();
+                floors.dedup();
+                let next = match self.brush {
+                    Brush::Floor(texture) => {
+                        floors.iter().position(|&t| t == texture).map(|i| i + 1)
+                    }
+                    _ => None,
+                };
+                match floors.get(next.unwrap_or(0) % floors.len().max(1)) {
+                    Some(&texture) => Brush::Floor(texture),
+                    None => return false,
+                }
+            }
+            KeyCode::R => Brush::Door,
+            KeyCode::T => Brush::Torch,
+            KeyCode::G => {
+                let next = match self.brush {
+                    Brush::Sprite(kind) => kind as usize + 1,
+                    _ => 0,
+                };
+                Brush::Sprite(SpriteType::ALL[next % SpriteType::ALL.len()])
+            }
+            KeyCode::P => Brush::Spawn,
+            _ => match DIGITS.iter().position(|&key| key == keycode) {
+                Some(i) if palette.wall_color(Tile::Wall(i + 1)).is_some() => Brush::Wall(i + 1),
+                Some(i) => {
+                    self.status = format!("wall texture {} isn't in the palette", i + 1);
+                    return true;
+                }
+                None => return false,
+            },
+        };
+        self.status.clear();
+        true
+    }
+
+    /// Paints the brush on a cell and returns whether the level changed. Doors stay off the
+    /// border so that it remains closed, walls and doors stay off the spawn and the player, and
+    /// torches, sprites and the spawn go on empty cells.
+    pub fn paint(&self, level: &mut Level, player: &Player, (x, y): (usize, usize)) -> bool {
+        let map = &mut level.map;
+        let i = x + y * map.size.0;
+        let border = x == 0 || y == 0 || x == map.size.0 - 1 || y == map.size.1 - 1;
+        let centre = (x as f32 + 0.5, y as f32 + 0.5);
+        let spawn = (level.spawn.pos.0 as usize, level.spawn.pos.1 as usize);
+        let occupied = spawn == (x, y) || overlaps_cell(player.pos, PLAYER_RADIUS, (x, y));
+        match self.brush {
+            Brush::Wall(texture) => {
+                if occupied || map.tile(i) == Tile::Wall(texture) {
+                    return false;
+                }
+                map.doors.remove(&i);
+                map.push_walls.remove(&i);
+                map.walls[i] = texture;
+                map.solid[i] = true;
+                level.lights.retain(|&light| light != i);
+                level.exits.retain(|exit| exit.cell != i);
+                level.sprites.retain(|sprite| !in_cell(sprite, (x, y)));
+            }
+            Brush::Floor(texture) => {
+                if map.floors[i] == texture {
+                    return false;
+                }
+                map.floors[i] = texture;
+            }
+            Brush::Door => {
+                if border || occupied || map.doors.contains_key(&i) {
+                    return false;
+                }
+                map.add_door(i);
+                level.lights.retain(|&light| light != i);
+                level.exits.retain(|exit| exit.cell != i);
+                level.sprites.retain(|sprite| !in_cell(sprite, (x, y)));
+            }
+            _ if map.walls[i] != 0 => return false,
+            Brush::Torch => {
+                if level.lights.contains(&i) {
+                    return false;
+                }
+                level.lights.push(i);
+            }
+            Brush::Sprite(kind) => {
+                if level.sprites.iter().any(|sprite| in_cell(sprite, (x, y))) {
+                    return false;
+                }
+                level
+                    .sprites
+                    .push(Sprite::new(kind, Vector2::new(centre.0, centre.1)));
+            }
+            Brush::Spawn => {
+                if level.spawn.pos == centre || level.exits.iter().any(|exit| exit.cell == i) {
+                    return false;
+                }
+                level.spawn.pos = centre;
+            }
+        }
+        true
+    }
+
+    /// Clears a cell and returns whether the level changed: removes its sprites, else its
+    /// torch, else turns it into an empty cell. Border cells stay walls.
+    pub fn erase(&self, level: &mut Level, (x, y): (usize, usize)) -> bool {
+        let map = &mut level.map;
+        let i = x + y * map.size.0;
+        let sprites = level.sprites.len();
+        level.sprites.retain(|sprite| !in_cell(sprite, (x, y)));
+        if level.sprites.len() != sprites {
+            return true;
+        }
+        if let Some(light) = level.lights.iter().position(|&light| light == i) {
+            level.lights.remove(light);
+            return true;
+        }
+        let border = x == 0 || y == 0 || x == map.size.0 - 1 || y == map.size.1 - 1;
+        if border || map.walls[i] == 0 {
+            return false;
+        }
+        map.doors.remove(&i);
+        map.push_walls.remove(&i);
+        map.walls[i] = 0;
+        map.solid[i] = false;
+        true
+    }
+
+    /// Cell under `point` on a screen of `screen` size, if any.
+    pub fn cell_at(
+        &self,
+        screen: (f32, f32),
+        map_size: (usize, usize),
+        point: Vector2<f32>,
+    ) -> Option<(usize, usize)> {
+        let (cell_size, origin) = layout(screen, map_size);
+        let x = (point.x - origin[0]) / cell_size;
+        let y = (point.y - origin[1]) / cell_size;
+        if x < 0.0 || y < 0.0 || x >= map_size.0 as f32 || y >= map_size.1 as f32 {
+            None
+        } else {
+            Some((x as usize, y as usize))
+        }
+    }
+
+    pub fn draw(
+        &self,
+        ctx: &mut Context,
+        minimap: &mut Minimap,
+        level: &Level,
+        player: &Player,
+        hovered: Option<(usize, usize)>,
+    ) -> GameResult {
+        let screen = graphics::drawable_size(ctx);
+        let map = &level.map;
+        let (cell_size, origin) = layout(screen, map.size);
+        minimap.draw_grid(ctx, map, origin, cell_size)?;
+
+        let rect = |x: f32, y: f32, inset: f32| {
+            Rect::new(
+                origin[0] + x * cell_size + inset,
+                origin[1] + y * cell_size + inset,
+                cell_size - 2.0 * inset,
+                cell_size - 2.0 * inset,
+            )
+        };
+        let point = |x: f32, y: f32| [origin[0] + x * cell_size, origin[1] + y * cell_size];
+        let color = |rgba: [u8; 4], alpha: u8| Color::from_rgba(rgba[0], rgba[1], rgba[2], alpha);
+
+        let mut mesh = MeshBuilder::new();
+        for i in 0..map.walls.len() {
+            let (x, y) = ((i % map.size.0) as f32, (i / map.size.0) as f32);
+            let tile = match map.tile(i) {
+                Tile::Empty => {
+                    if let Some(floor) = level.palette.floor_color(map.floors[i]) {
+                        mesh.rectangle(
+                            DrawMode::fill(),
+                            rect(x, y, cell_size * 0.35),
+                            color(floor, 96),
+                        )?;
+                    }
+                    continue;
+                }
+                tile => tile,
+            };
+            if let Some(wall) = level.palette.wall_color(tile) {
+                mesh.rectangle(DrawMode::fill(), rect(x, y, 1.0), color(wall, 160))?;
+            }
+        }
+        for &light in &level.lights {
+            let (x, y) = (light % map.size.0, light / map.size.0);
+            mesh.circle(
+                DrawMode::fill(),
+                point(x as f32 + 0.5, y as f32 + 0.5),
+                cell_size * 0.3,
+                0.5,
+                Color::YELLOW,
+            )?;
+        }
+        for sprite in &level.sprites {
+            mesh.circle(
+                DrawMode::fill(),
+                point(sprite.pos.x, sprite.pos.y),
+                cell_size * 0.2,
+                0.5,
+                Color::CYAN,
+            )?;
+        }
+        mesh.circle(
+            DrawMode::stroke(2.0),
+            point(level.spawn.pos.0, level.spawn.pos.1),
+            cell_size * 0.35,
+            0.5,
+            Color::GREEN,
+        )?;
+        let pos = point(player.pos.x, player.pos.y);
+        let facing = player.pos + player.dir_norm * 0.75;
+        mesh.circle(DrawMode::fill(), pos, cell_size * 0.2, 0.5, Color::RED)?;
+        mesh.line(&[pos, point(facing.x, facing.y)], 2.0, Color::RED)?;
+        if let Some((x, y)) = hovered {
+            mesh.rectangle(
+                DrawMode::stroke(2.0),
+                rect(x as f32, y as f32, 0.0),
+                Color::WHITE,
+            )?;
+        }
+        let mesh = mesh.build(ctx)?;
+        graphics::draw(ctx, &mesh, DrawParam::default())?;
+
+        let text = graphics::Text::new(format!(
+            "Editor - brush: {:?} - {}\n{}",
+            self.brush, HELP, self.status
+        ));
+        graphics::draw(
+            ctx,
+            &text,
+            DrawParam::new().dest([0.0, 20.0]).color(Color::WHITE),
+        )
+    }
+}
+
+fn in_cell(sprite: &Sprite, (x, y): (usize, usize)) -> bool {
+    sprite.pos.x as usize == x && sprite.pos.y as usize == y
+}
+
+/// Size of a cell and top left corner of the grid, centred on the screen.
+fn layout(screen: (f32, f32), map_size: (usize, usize)) -> (f32, [f32; 2]) {
+    let cell_size = ((screen.0 - 2.0 * MARGIN) / map_size.0 as f32)
+        .min((screen.1 - 2.0 * MARGIN) / map_size.1 as f32)
+        .clamp(1.0, MAX_CELL_SIZE)
+        .floor();
+    let origin = [
+        ((screen.0 - cell_size * map_size.0 as f32) * 0.5).floor(),
+        ((screen.1 - cell_size * map_size.1 as f32) * 0.5).floor(),
+    ];
+    (cell_size, origin)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::{LevelDescription, SpawnPoint};
+    use crate::map::Map;
+
+    fn level() -> Level {
+        let (map, spawn) = Map::from_ascii(
+            "
+            #####
+            #P..#
+            #...#
+            #####
+            ",
+        )
+        .unwrap();
+        let spawn = spawn.unwrap();
+        let description = LevelDescription {
+            walls: String::new(),
+            floors: String::new(),
+            spawn: SpawnPoint {
+                pos: (spawn.x, spawn.y),
+                dir: (0.0, -1.0),
+            },
+            sprites: Vec::new(),
+            lights: Vec::new(),
+            doors: Vec::new(),
+            exits: Vec::new(),
+            surfaces: Default::default(),
+            palette: Palette::default(),
+        };
+        Level::new(description, map).unwrap()
+    }
+
+    #[test]
+    fn paints_and_erases_cells() {
+        let mut level = level();
+        let mut editor = Editor::default();
+        let dir = Vector2::new(0.0, -1.0);
+        let player = Player::new(Vector2::new(2.5, 1.5), dir, dir, 1.0, 0.0, 0.0);
+
+        assert!(editor.select_brush(KeyCode::Key3, &level.palette));
+        assert!(editor.paint(&mut level, &player, (2, 2)));
+        assert!(!editor.paint(&mut level, &player, (2, 2)));
+        assert_eq!(level.map.walls[2 + 2 * 5], 3);
+        assert!(level.map.solid[2 + 2 * 5]);
+
+        // The spawn and the player are never buried, sprites under a new wall are removed.
+        assert!(!editor.paint(&mut level, &player, (1, 1)));
+        assert!(!editor.paint(&mut level, &player, (2, 1)));
+        assert!(!level.map.solid[1 + 5] && !level.map.solid[2 + 5]);
+        level
+            .sprites
+            .push(Sprite::new(SpriteType::Armor, Vector2::new(1.5, 2.5)));
+        assert!(editor.paint(&mut level, &player, (1, 2)));
+        assert!(level.sprites.is_empty());
+
+        editor.brush = Brush::Torch;
+        assert!(!editor.paint(&mut level, &player, (2, 2)));
+        assert!(editor.paint(&mut level, &player, (3, 2)));
+        assert_eq!(level.lights, vec![3 + 2 * 5]);
+
+        editor.brush = Brush::Door;
+        assert!(!editor.paint(&mut level, &player, (4, 2)));
+        assert!(!editor.paint(&mut level, &player, (2, 1)));
+        assert!(editor.paint(&mut level, &player, (3, 1)));
+        assert!(level.map.doors.contains_key(&(3 + 5)));
+
+        assert!(editor.erase(&mut level, (3, 2)));
+        assert!(level.lights.is_empty());
+        assert!(editor.erase(&mut level, (2, 2)));
+        assert!(!level.map.solid[2 + 2 * 5]);
+        assert!(!editor.erase(&mut level, (0, 2)));
+        assert!(level.map.check_border().is_ok());
+    }
+
+    #[test]
+    fn brushes_cycle_through_palette_and_sprites() {
+        let palette = Palette::default();
+        let mut editor = Editor::default();
+
+        assert!(editor.select_brush(KeyCode::F, &palette));
+        assert_eq!(editor.brush, Brush::Floor(0));
+        assert!(editor.select_brush(KeyCode::F, &palette));
+        assert_eq!(editor.brush, Brush::Floor(1));
+        assert!(editor.select_brush(KeyCode::F, &palette));
+        assert_eq!(editor.brush, Brush::Floor(0));
+
+        editor.brush = Brush::Sprite(SpriteType::YellowKey);
+        assert!(editor.select_brush(KeyCode::G, &palette));
+        assert_eq!(editor.brush, Brush::Sprite(SpriteType::Armor));
+
+        // Texture 7 has no color in the default palette, so the level couldn't be saved.
+        assert!(editor.select_brush(KeyCode::Key7, &palette));
+        assert_eq!(editor.brush, Brush::Sprite(SpriteType::Armor));
+        assert!(!editor.status.is_empty());
+        assert!(!editor.select_brush(KeyCode::Z, &palette));
+    }
+
+    #[test]
+    fn maps_screen_points_to_cells() {
+        let editor = Editor::default();
+
+        // 10x5 map on 600x400: cells of 48 pixels, grid from (60, 80) to (540, 320).
+        assert_eq!(
+            editor.cell_at((600.0, 400.0), (10, 5), Vector2::new(61.0, 81.0)),
+            Some((0, 0))
+        );
+        assert_eq!(
+            editor.cell_at((600.0, 400.0), (10, 5), Vector2::new(539.0, 319.0)),
+            Some((9, 4))
+        );
+        assert_eq!(
+            editor.cell_at((600.0, 400.0), (10, 5), Vector2::new(59.0, 100.0)),
+            None
+        );
+    }
+}
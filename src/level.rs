@@ -0,0 +1,592 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::Path;
+
+use ggez::{filesystem, Context, GameError, GameResult};
+use serde::{Deserialize, Serialize};
+
+use crate::collision::SPRITE_RADIUS;
+use crate::lighting::Lighting;
+use crate::map::{DoorKind, Key, Map, DOOR_HOLD, DOOR_SPEED};
+use crate::movement::Surface;
+use crate::palette::Palette;
+use crate::player::Player;
+use crate::sprite::{Sprite, SpriteType};
+use crate::tiled;
+use crate::utilities::vector2::Vector2;
+
+/// Contents of a level file, written in RON.
+///
+/// The wall and floor layers are images whose paths are relative to the level file. Every
+/// position is in map cells, with `(0, 0)` at the top left corner of the map.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LevelDescription {
+    pub walls: String,
+    pub floors: String,
+    pub spawn: SpawnPoint,
+    #[serde(default)]
+    pub sprites: Vec<SpritePlacement>,
+    /// Cells that light up the level.
+    #[serde(default)]
+    pub lights: Vec<(usize, usize)>,
+    /// Doors on top of the ones painted in the wall layer.
+    #[serde(default)]
+    pub doors: Vec<DoorPlacement>,
+    /// Cells that take the player to another level.
+    #[serde(default)]
+    pub exits: Vec<ExitPlacement>,
+    /// Floor textures the player moves differently on, see [`Surface`].
+    #[serde(default)]
+    pub surfaces: BTreeMap<usize, Surface>,
+    /// Colors of the wall and floor layers, see [`Palette`].
+    #[serde(default)]
+    pub palette: Palette,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SpawnPoint {
+    pub pos: (f32, f32),
+    pub dir: (f32, f32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SpritePlacement {
+    pub kind: SpriteType,
+    pub pos: (f32, f32),
+}
+
+/// Door added to the level, or a door of the wall layer given its own speed, hold time, lock or
+/// kind.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DoorPlacement {
+    pub pos: (usize, usize),
+    /// Share of the doorway the door slides across each second.
+    #[serde(default = "door_speed")]
+    pub speed: f32,
+    /// Seconds the door stays open before closing by itself, `None` to stay open until used.
+    #[serde(default = "door_hold")]
+    pub hold: Option<f32>,
+    /// Key the player needs to open the door, e.g. `lock: Some(Red)`.
+    #[serde(default)]
+    pub lock: Option<Key>,
+    /// How the door opens, e.g. `kind: Lift` for a portcullis.
+    #[serde(default)]
+    pub kind: DoorKind,
+}
+
+fn door_speed() -> f32 {
+    DOOR_SPEED
+}
+
+fn door_hold() -> Option<f32> {
+    Some(DOOR_HOLD)
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExitPlacement {
+    pub pos: (usize, usize),
+    /// Level file the exit leads to, relative to this one. Without it the exit leads to the
+    /// next level of the campaign.
+    #[serde(default)]
+    pub to: Option<String>,
+}
+
+/// Cell that ends the level when the player steps on it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Exit {
+    pub cell: usize,
+    pub to: Option<String>,
+}
+
+/// A level ready to be played: its map, sprites, light sources and where the player starts.
+pub struct Level {
+    pub map: Map,
+    pub sprites: Vec<Sprite>,
+    pub lights: Vec<usize>,
+    pub spawn: SpawnPoint,
+    pub exits: Vec<Exit>,
+    /// Palette the wall and floor layers are written with when the level is saved.
+    pub palette: Palette,
+}
+
+impl Level {
+    /// Loads a level file through the ggez filesystem, e.g. `/level1.ron` from the assets.
+    /// Tiled maps ending in `.tmx` or `.tmj` are imported as well.
+    pub fn load(ctx: &mut Context, path: &Path) -> GameResult<Self> {
+        let mut text = String::new();
+        filesystem::open(ctx, path)?.read_to_string(&mut text)?;
+        if let Some(level) = import(&text, path) {
+            return level;
+        }
+        let description = parse(&text, path)?;
+
+        let dir = path.parent().unwrap_or_else(|| Path::new("/"));
+        let map = Map::new(
+            ctx,
+            &dir.join(&description.walls),
+            &dir.join(&description.floors),
+            &description.palette,
+        )?;
+        Self::new(description, map)
+    }
+
+    /// Loads a level file straight from disk, without needing a `ggez::Context`.
+    pub fn load_file(path: &Path) -> GameResult<Self> {
+        let text = std::fs::read_to_string(path)?;
+        if let Some(level) = import(&text, path) {
+            return level;
+        }
+        let description = parse(&text, path)?;
+
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let map = Map::load_file(
+            &dir.join(&description.walls),
+            &dir.join(&description.floors),
+            &description.palette,
+        )?;
+        Self::new(description, map)
+    }
+
+    /// Builds a level from its description and the map made from its layers.
+    pub fn new(description: LevelDescription, mut map: Map) -> GameResult<Self> {
+        let size = map.size;
+        let cell = |(x, y): (usize, usize), what: &str| {
+            if x < size.0 && y < size.1 {
+                Ok(x + y * size.0)
+            } else {
+                Err(GameError::ResourceLoadError(format!(
+                    "{} at ({}, {}) is outside the {}x{} map",
+                    what, x, y, size.0, size.1
+                )))
+            }
+        };
+
+        let (x, y) = description.spawn.pos;
+        if x < 0.0 || y < 0.0 {
+            return Err(GameError::ResourceLoadError(format!(
+                "spawn at ({}, {}) is outside the map",
+                x, y
+            )));
+        }
+        let spawn = cell((x as usize, y as usize), "spawn")?;
+
+        map.surfaces = description.surfaces;
+        for placement in &description.doors {
+            let (x, y) = placement.pos;
+            if placement.speed <= 0.0 || placement.hold.is_some_and(|hold| hold < 0.0) {
+                return Err(GameError::ResourceLoadError(format!(
+                    "door at ({}, {}) needs a speed above 0 and a hold time of at least 0",
+                    x, y
+                )));
+            }
+            let door = map.add_door(cell(placement.pos, "door")?);
+            door.speed = placement.speed;
+            door.hold = placement.hold;
+            door.lock = placement.lock;
+            door.kind = placement.kind;
+        }
+        map.check_border()?;
+        map.check_textures()?;
+        if map.solid[spawn] || map.doors.contains_key(&spawn) {
+            return Err(GameError::ResourceLoadError(format!(
+                "spawn at ({}, {}) is inside a wall",
+                x, y
+            )));
+        }
+
+        let lights = description
+            .lights
+            .iter()
+            .map(|&pos| cell(pos, "light"))
+            .collect::<GameResult<_>>()?;
+
+        let exits: Vec<Exit> = description
+            .exits
+            .iter()
+            .map(|exit| {
+                let i = cell(exit.pos, "exit")?;
+                let (x, y) = exit.pos;
+                if map.solid[i] || map.doors.contains_key(&i) {
+                    return Err(GameError::ResourceLoadError(format!(
+                        "exit at ({}, {}) is inside a wall",
+                        x, y
+                    )));
+                }
+                if i == spawn {
+                    return Err(GameError::ResourceLoadError(format!(
+                        "exit at ({}, {}) is on the spawn",
+                        x, y
+                    )));
+                }
+                Ok(Exit {
+                    cell: i,
+                    to: exit.to.clone(),
+                })
+            })
+            .collect::<GameResult<_>>()?;
+
+        let sprites = description
+            .sprites
+            .iter()
+            .map(|sprite| Sprite::new(sprite.kind, Vector2::new(sprite.pos.0, sprite.pos.1)))
+            .collect();
+
+        Ok(Self {
+            map,
+            sprites,
+            lights,
+            spawn: description.spawn,
+            exits,
+            palette: description.palette,
+        })
+    }
+
+    /// Writes the level file with its wall and floor layers next to it, named after the file,
+    /// e.g. `level1.ron` with `level1-walls.png` and `level1-floors.png`. Ceilings aren't saved.
+    pub fn save_file(&self, path: &Path) -> GameResult {
+        let stem = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("level");
+        let walls = format!("{}-walls.png", stem);
+        let floors = format!("{}-floors.png", stem);
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        self.map
+            .save_file(&dir.join(&walls), &dir.join(&floors), &self.palette)?;
+
+        let width = self.map.size.0;
+        let description = LevelDescription {
+            walls,
+            floors,
+            spawn: self.spawn,
+            sprites: self
+                .sprites
+                .iter()
+                .map(|sprite| SpritePlacement {
+                    kind: SpriteType::ALL[sprite.stype],
+                    pos: (sprite.pos.x, sprite.pos.y),
+                })
+                .collect(),
+            lights: self
+                .lights
+                .iter()
+                .map(|&light| (light % width, light / width))
+                .collect(),
+            // Doors are painted in the wall layer, only the ones set apart are listed.
+            doors: self.door_placements(),
+            exits: self
+                .exits
+                .iter()
+                .map(|exit| ExitPlacement {
+                    pos: (exit.cell % width, exit.cell / width),
+                    to: exit.to.clone(),
+                })
+                .collect(),
+            surfaces: self.map.surfaces.clone(),
+            palette: self.palette.clone(),
+        };
+        let text = ron::ser::to_string_pretty(&description, ron::ser::PrettyConfig::default())
+            .map_err(|e| GameError::CustomError(format!("{}: {}", path.display(), e)))?;
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+
+    /// Doors that are locked or whose speed, hold time or kind isn't the default, by position.
+    fn door_placements(&self) -> Vec<DoorPlacement> {
+        let width = self.map.size.0;
+        let mut doors: Vec<DoorPlacement> = self
+            .map
+            .doors
+            .values()
+            .filter(|door| {
+                door.speed != DOOR_SPEED
+                    || door.hold != Some(DOOR_HOLD)
+                    || door.lock.is_some()
+                    || door.kind != DoorKind::Split
+            })
+            .map(|door| DoorPlacement {
+                pos: (door.pos % width, door.pos / width),
+                speed: door.speed,
+                hold: door.hold,
+                lock: door.lock,
+                kind: door.kind,
+            })
+            .collect();
+        doors.sort_by_key(|door| (door.pos.1, door.pos.0));
+        doors
+    }
+
+    /// Moves every door on by a tick, keeping those with the player or a sprite in their
+    /// doorway from closing.
+    pub fn update_doors(&mut self, time: f32, player: &Player) {
+        let mut bodies = vec![(player.pos, player.radius)];
+        bodies.extend(self.sprites.iter().map(|sprite| {
+            let radius = if sprite.solid() { SPRITE_RADIUS } else { 0.0 };
+            (sprite.pos, radius)
+        }));
+        self.map.update_doors(time, &bodies);
+    }
+
+    /// Exit in the cell at `pos`, if any.
+    pub fn exit_at(&self, pos: Vector2<f32>) -> Option<&Exit> {
+        if pos.x < 0.0 || pos.y < 0.0 {
+            return None;
+        }
+        let cell = pos.x as usize + pos.y as usize * self.map.size.0;
+        self.exits.iter().find(|exit| exit.cell == cell)
+    }
+
+    pub fn lighting(&self) -> Lighting {
+        Lighting::new(self.lights.clone(), &self.map.solid, self.map.size)
+    }
+}
+
+/// Imports Tiled maps, told apart from level files by their extension.
+fn import(text: &str, path: &Path) -> Option<GameResult<Level>> {
+    match path.extension()?.to_str()? {
+        "tmx" => Some(tiled::from_tmx(text)),
+        "tmj" => Some(tiled::from_tmj(text)),
+        _ => None,
+    }
+}
+
+fn parse(text: &str, path: &Path) -> GameResult<LevelDescription> {
+    ron::from_str(text)
+        .map_err(|e| GameError::ResourceLoadError(format!("{}: {}", path.display(), e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 5x5 room of black walls around empty cells.
+    fn room() -> Map {
+        let walls: Vec<u8> = (0..25)
+            .flat_map(|i| {
+                let (x, y) = (i % 5, i / 5);
+                if x % 4 == 0 || y % 4 == 0 {
+                    [0, 0, 0, 255]
+                } else {
+                    [255, 255, 255, 255]
+                }
+            })
+            .collect();
+        Map::from_rgba8(&walls, &[255; 25 * 4], (5, 5), &Palette::default()).unwrap()
+    }
+
+    #[test]
+    fn loads_level_file() {
+        let level = Level::load_file(Path::new("assets/level1.ron")).unwrap();
+
+        assert_eq!(level.map.size, (16, 25));
+        assert_eq!(level.spawn.pos, (8.5, 12.5));
+        assert_eq!(level.lights, vec![1 + 16 * 3, 14 + 16 * 7, 8 + 16 * 23]);
+        assert_eq!(level.sprites.len(), 7);
+        assert!(level.map.doors.len() >= 2);
+    }
+
+    #[test]
+    fn loads_tiled_maps() {
+        let level = Level::load_file(Path::new("tests/fixtures/level.tmx")).unwrap();
+
+        assert_eq!(level.map.size, (10, 10));
+        assert_eq!(level.sprites.len(), 3);
+    }
+
+    #[test]
+    fn rejects_objects_outside_the_map() {
+        let description: LevelDescription = ron::from_str(
+            r#"(
+                walls: "walls.png",
+                floors: "floors.png",
+                spawn: (pos: (1.5, 1.5), dir: (0.0, -1.0)),
+                lights: [(5, 0)],
+            )"#,
+        )
+        .unwrap();
+
+        match Level::new(description, room()) {
+            Err(GameError::ResourceLoadError(e)) => assert!(e.contains("light at (5, 0)")),
+            _ => panic!("light outside the map was accepted"),
+        }
+    }
+
+    #[test]
+    fn rejects_textures_missing_from_the_atlas() {
+        let description: LevelDescription = ron::from_str(
+            r#"(
+                walls: "walls.png",
+                floors: "floors.png",
+                spawn: (pos: (1.5, 1.5), dir: (0.0, -1.0)),
+            )"#,
+        )
+        .unwrap();
+        let mut map = room();
+        map.ceilings[2 + 5] = 40;
+
+        match Level::new(description, map) {
+            Err(GameError::ResourceLoadError(e)) => {
+                assert!(e.contains("ceiling at (2, 1) uses texture 40"), "{}", e)
+            }
+            _ => panic!("missing texture was accepted"),
+        }
+    }
+
+    #[test]
+    fn places_doors_from_description() {
+        let description: LevelDescription = ron::from_str(
+            r#"(
+                walls: "walls.png",
+                floors: "floors.png",
+                spawn: (pos: (1.5, 1.5), dir: (0.0, -1.0)),
+                sprites: [(kind: Armor, pos: (3.5, 1.5))],
+                doors: [
+                    (pos: (2, 2)),
+                    (pos: (2, 1), speed: 2.0, hold: None, lock: Some(Red), kind: Lift),
+                ],
+            )"#,
+        )
+        .unwrap();
+
+        let level = Level::new(description, room()).unwrap();
+
+        assert_eq!(level.map.walls[12], 6);
+        assert!(level.map.solid[12]);
+        assert_eq!(level.map.doors[&12].speed, DOOR_SPEED);
+        assert_eq!(level.map.doors[&12].hold, Some(DOOR_HOLD));
+        assert_eq!(
+            (level.map.doors[&7].speed, level.map.doors[&7].hold),
+            (2.0, None)
+        );
+        assert_eq!(level.map.doors[&7].lock, Some(Key::Red));
+        assert_eq!(level.map.doors[&12].lock, None);
+        assert_eq!(level.map.doors[&7].kind, DoorKind::Lift);
+        assert_eq!(level.map.doors[&12].kind, DoorKind::Split);
+        assert_eq!(level.door_placements().len(), 1);
+        assert_eq!(level.sprites[0].pos, Vector2::new(3.5, 1.5));
+    }
+
+    #[test]
+    fn rejects_doors_on_the_border() {
+        let description: LevelDescription = ron::from_str(
+            r#"(
+                walls: "walls.png",
+                floors: "floors.png",
+                spawn: (pos: (1.5, 1.5), dir: (0.0, -1.0)),
+                doors: [(pos: (4, 2))],
+            )"#,
+        )
+        .unwrap();
+
+        match Level::new(description, room()) {
+            Err(GameError::ResourceLoadError(e)) => {
+                assert_eq!(e, "map border is open at (4, 2)")
+            }
+            _ => panic!("door on the border was accepted"),
+        }
+    }
+
+    #[test]
+    fn finds_exits_under_the_player() {
+        let description: LevelDescription = ron::from_str(
+            r#"(
+                walls: "walls.png",
+                floors: "floors.png",
+                spawn: (pos: (1.5, 1.5), dir: (0.0, -1.0)),
+                exits: [(pos: (3, 3), to: Some("level2.ron")), (pos: (1, 3))],
+            )"#,
+        )
+        .unwrap();
+
+        let level = Level::new(description, room()).unwrap();
+
+        let exit = level.exit_at(Vector2::new(3.2, 3.9)).unwrap();
+        assert_eq!(exit.to.as_deref(), Some("level2.ron"));
+        assert_eq!(level.exit_at(Vector2::new(1.5, 3.5)).unwrap().to, None);
+        assert!(level.exit_at(Vector2::new(1.5, 1.5)).is_none());
+    }
+
+    #[test]
+    fn rejects_exits_inside_walls() {
+        let description: LevelDescription = ron::from_str(
+            r#"(
+                walls: "walls.png",
+                floors: "floors.png",
+                spawn: (pos: (1.5, 1.5), dir: (0.0, -1.0)),
+                exits: [(pos: (0, 2))],
+            )"#,
+        )
+        .unwrap();
+
+        match Level::new(description, room()) {
+            Err(GameError::ResourceLoadError(e)) => {
+                assert_eq!(e, "exit at (0, 2) is inside a wall")
+            }
+            _ => panic!("exit inside a wall was accepted"),
+        }
+    }
+
+    #[test]
+    fn rejects_spawns_inside_walls() {
+        let description: LevelDescription = ron::from_str(
+            r#"(
+                walls: "walls.png",
+                floors: "floors.png",
+                spawn: (pos: (0.5, 2.5), dir: (0.0, -1.0)),
+            )"#,
+        )
+        .unwrap();
+
+        match Level::new(description, room()) {
+            Err(GameError::ResourceLoadError(e)) => {
+                assert_eq!(e, "spawn at (0.5, 2.5) is inside a wall")
+            }
+            _ => panic!("spawn inside a wall was accepted"),
+        }
+    }
+
+    #[test]
+    fn reads_the_surfaces_of_floors() {
+        let description: LevelDescription = ron::from_str(
+            r#"(
+                walls: "walls.png",
+                floors: "floors.png",
+                spawn: (pos: (1.5, 1.5), dir: (0.0, -1.0)),
+                surfaces: { 0: (acceleration: 3.0, friction: 1.0) },
+            )"#,
+        )
+        .unwrap();
+
+        let level = Level::new(description, room()).unwrap();
+
+        let ice = level.map.surface(Vector2::new(1.5, 1.5));
+        assert_eq!((ice.acceleration, ice.friction), (3.0, 1.0));
+        assert_eq!(ice.max_speed, Surface::default().max_speed);
+    }
+
+    #[test]
+    fn saved_level_loads_back() {
+        let level = Level::load_file(Path::new("assets/level1.ron")).unwrap();
+        let dir = std::env::temp_dir().join(format!("raycaster-{}-level", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("saved.ron");
+
+        level.save_file(&path).unwrap();
+        let saved = Level::load_file(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(saved.map.walls, level.map.walls);
+        assert_eq!(saved.map.floors, level.map.floors);
+        assert_eq!(saved.map.doors.len(), level.map.doors.len());
+        assert_eq!(saved.lights, level.lights);
+        assert_eq!(saved.spawn, level.spawn);
+        assert_eq!(saved.exits, level.exits);
+        assert_eq!(saved.map.surfaces, level.map.surfaces);
+        assert_eq!(saved.palette, level.palette);
+        for (saved, sprite) in saved.sprites.iter().zip(&level.sprites) {
+            assert_eq!(saved.stype, sprite.stype);
+            assert_eq!(saved.pos, sprite.pos);
+        }
+        assert_eq!(saved.sprites.len(), level.sprites.len());
+    }
+}
@@ -1,656 +1,505 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use ggez::event::{EventHandler, KeyCode, KeyMods};
+use ggez::event::{EventHandler, KeyCode, KeyMods, MouseButton};
 use ggez::graphics::{self, Color, DrawParam};
-use ggez::input::keyboard::is_key_pressed;
-use ggez::{timer, Context, GameResult};
-mod lighting;
-mod map;
-mod player;
-mod screen;
-mod sprite;
-mod utilities;
+use ggez::input::mouse::button_pressed;
+use ggez::{timer, Context, GameError, GameResult};
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
+pub mod campaign;
+pub mod collision;
+pub mod controls;
+pub mod demo;
+pub mod editor;
+pub mod level;
+pub mod lighting;
+pub mod map;
+pub mod minimap;
+pub mod movement;
+pub mod palette;
+pub mod player;
+pub mod renderer;
+pub mod savegame;
+pub mod screen;
+pub mod sprite;
+pub mod tiled;
+pub mod timestep;
+pub mod utilities;
+use campaign::{Campaign, Transition};
+use controls::{Action, FrameInput, Keymap};
+use demo::{Demo, DemoMode};
+use editor::Editor;
+use level::{Exit, Level};
 use lighting::{Lighting, Torch};
-use map::Map;
-use num::clamp;
+use map::Key;
+use minimap::Minimap;
 use player::Player;
-use rayon::prelude::*;
-use screen::Screen;
-use sprite::Sprite;
-use utilities::input::{mouse_grabbed_and_hidden, set_mouse_location};
+use renderer::{RenderSettings, Renderer};
+use savegame::{RngState, SaveGame, QUICKSAVE};
+use timestep::{Timestep, TICK};
+use utilities::input::{mouse_grabbed_and_hidden, mouse_location, set_mouse_location};
 use utilities::vector2::Vector2;
 //https://mynoise.net/NoiseMachines/dungeonRPGSoundscapeGenerator.php?l=32343600005816020035&mt=1&tm=1
-use crate::utilities::input::get_delta;
 
 const PI: f32 = std::f32::consts::PI;
-const RAYSPERPIXEL: usize = 2;
-const FOV: f32 = 45.0;
+/// Generator of the random numbers of the game, the same as `rand`'s `StdRng` but with a
+/// position in its stream that saved games can keep.
+pub type GameRng = ChaCha12Rng;
+/// Seconds a notice stays on screen.
+const NOTICE_TIME: f32 = 2.0;
+/// Directory the game loads its resources from, and where the editor saves levels.
+pub const ASSETS_DIR: &str = "assets";
+
 pub struct MainState {
     player: Player,
-    map_size: (usize, usize),
-    cell_size: f32,
-    map: Map,
-    angles: Vec<f32>,
-    buffer_floors: Vec<f32>,
-    buffer_walking: Vec<f32>,
+    level: Level,
+    /// Path of the level in the resources, e.g. `/level1.ron`.
+    level_path: PathBuf,
+    minimap: Minimap,
     sky: Sky,
-    intersections: Intersections,
-    screen: Screen,
-    sprites: Vec<Sprite>,
+    renderer: Renderer,
     time: f32,
     lighting: Lighting,
     torch: Torch,
+    editor: Editor,
+    campaign: Campaign,
+    /// Set while moving to another level.
+    transition: Option<Transition>,
+    /// Message shown at the bottom of the screen, with the time it was posted.
+    notice: Option<(String, f32)>,
+    demo: Option<DemoMode>,
+    timestep: Timestep,
+    /// Seed of `rng`, kept for the demos.
+    seed: u64,
+    /// Source of every random number in the game, so that a seed replays the same game.
+    rng: GameRng,
+    keymap: Keymap,
+    /// Position and camera height of the player at the previous tick, to draw them in between.
+    previous: (Vector2<f32>, f32),
 }
 
 impl MainState {
-    pub fn new(ctx: &mut Context) -> GameResult<Self> {
+    /// Starts the game on the first level of `campaign`, with random numbers drawn from `seed`.
+    pub fn new(
+        ctx: &mut Context,
+        settings: RenderSettings,
+        campaign: Campaign,
+        seed: u64,
+        keymap: Keymap,
+    ) -> GameResult<Self> {
         let (w, h) = graphics::drawable_size(ctx);
         graphics::set_fullscreen(ctx, ggez::conf::FullscreenType::Desktop)?;
-        let pos = Vector2::new(8.5, 12.5);
-        let dir_norm = Vector2::new(0.0f32, -1.0); // Player direction
-        let plane = Vector2::new((FOV.to_radians() * 0.5).tan(), 0.0); //Camera plane vector
-        let map_size = (16, 25);
-        let cell_size = 128.0;
-        let minimap = graphics::Image::new(ctx, "/minimap.png")?;
+        let level_path = campaign.first().to_path_buf();
+        let level = Level::load(ctx, &level_path)?;
+        let pos = Vector2::new(level.spawn.pos.0, level.spawn.pos.1);
+        let mut dir_norm = Vector2::new(level.spawn.dir.0, level.spawn.dir.1); // Player direction
+        dir_norm.normalize();
+        let plane = settings.plane(dir_norm); //Camera plane vector
+        let minimap_image = graphics::Image::new(ctx, "/minimap.png")?;
         let minimap_sb =
             graphics::spritebatch::SpriteBatch::new(graphics::Image::new(ctx, "/sb.png")?);
-        let map = Map::new(
-            ctx,
-            Path::new("/map.png"),
-            Path::new("/floor.png"),
-            minimap,
-            minimap_sb,
-            map_size,
-        )?;
+        let minimap = Minimap::new(ctx, minimap_image, minimap_sb)?;
 
         let player = Player::new(
-            ctx,
             pos,
             dir_norm,
             plane,
-            (w * 0.5) / ((FOV.to_radians() * 0.5).tan()), //distance from the player to the projection plane
+            settings.planedist(), //distance from the player to the projection plane
             0.0,
             0.0,
-        )?;
+        );
 
         set_mouse_location(ctx, Vector2::new(w * 0.5, h * 0.5)).unwrap();
 
-        let angles: Vec<f32> = (0..w as usize / RAYSPERPIXEL)
-            .map(|r: usize| {
-                ((r as f32 * w / (w / (RAYSPERPIXEL as f32) - 1.0) - w * 0.5) / player.planedist)
-                    .atan()
-            })
-            .collect();
-
-        let buffer_floors = (0..h as usize)
-            .map(|y| player.planedist / (2.0 * y as f32 - h))
-            .collect();
-
-        let buffer_walking = (0..150)
-            .map(|i| ((i as f32) / 150.0 * 2.0 * PI).sin())
-            .collect();
-
         let mut skyimg = graphics::Image::new(ctx, "/sky2.png")?;
         skyimg.set_wrap(graphics::WrapMode::Tile, graphics::WrapMode::Mirror);
         skyimg.set_filter(graphics::FilterMode::Nearest);
         let mut sb = graphics::spritebatch::SpriteBatch::new(skyimg);
         let idx = sb.add(DrawParam::default());
         let sky = Sky { sb, idx };
-        let intersections = Intersections::new(w as usize);
 
         let wall_textures = graphics::Image::new(ctx, "/wall128.png")?.to_rgba8(ctx)?;
 
         let sprite_textures = graphics::Image::new(ctx, "/sprite128.png")?.to_rgba8(ctx)?;
 
-        let mut screen = Screen::new(h, w, 128, 128 * 8);
-        screen.textures(wall_textures, sprite_textures);
-
-        let sprites = vec![
-            Sprite::new(sprite::SpriteType::Armor, Vector2::new(7.5, 7.5)),
-            Sprite::new(sprite::SpriteType::Armor, Vector2::new(7.5, 9.5)),
-            //Sprite::new(sprite::SpriteType::CandleHolder, Vector2::new(12.5, 12.5)),
-            Sprite::new(sprite::SpriteType::Bat, Vector2::new(6.5, 12.5)),
-            Sprite::new(sprite::SpriteType::Torch, Vector2::new(9.0, 15.0 - 0.048)),
-            Sprite::new(sprite::SpriteType::Torch, Vector2::new(8.5, 24.0 - 0.048)),
-            Sprite::new(sprite::SpriteType::Torch, Vector2::new(8.048, 12.0)),
-            Sprite::new(sprite::SpriteType::Gore, Vector2::new(13.0, 3.0)),
-        ];
-
-        let lighting = lighting::Lighting::new(
-            vec![1 + map_size.0 * 3, 14 + map_size.0 * 7, 8 + map_size.0 * 23],
-            &map.solid,
-            map_size,
-        );
+        let renderer = Renderer::new(settings, wall_textures, sprite_textures);
 
-        let torch = lighting::Torch::default();
+        let lighting = level.lighting();
+
+        let mut rng = GameRng::seed_from_u64(seed);
+        let torch = Torch::new(&mut rng);
 
         Ok(Self {
             player,
-            map_size,
-            cell_size,
-            map,
-            angles,
-            buffer_floors,
-            buffer_walking,
+            level,
+            level_path,
+            minimap,
             sky,
-            intersections,
-            screen,
-            sprites,
+            renderer,
             time: 0.0,
             lighting,
             torch,
+            editor: Editor::default(),
+            campaign,
+            transition: None,
+            notice: None,
+            demo: None,
+            timestep: Timestep::default(),
+            seed,
+            rng,
+            keymap,
+            previous: (pos, 0.0),
         })
     }
 
-    pub fn handle_input(&mut self, ctx: &mut Context, dir_norm: Vector2<f32>) {
-        let (w, h) = graphics::drawable_size(ctx);
-        let dt = ggez::timer::delta(ctx).as_secs_f32();
-        mouse_grabbed_and_hidden(ctx, false, true).unwrap();
+    /// Replaces the current level, its map, sprites, doors and lights, with the one at `path`.
+    /// The player keeps everything but where they stand and look.
+    fn enter_level(&mut self, ctx: &mut Context, path: PathBuf) -> GameResult {
+        let level = Level::load(ctx, &path)?;
+        self.player.pos = Vector2::new(level.spawn.pos.0, level.spawn.pos.1);
+        self.player.dir_norm = Vector2::new(level.spawn.dir.0, level.spawn.dir.1);
+        self.player.dir_norm.normalize();
+        self.player.set_projection(&self.renderer.settings());
+        self.replace_level(level, path);
+        Ok(())
+    }
 
-        let mut delta_mouse_loc_x = get_delta(ctx).x;
-        let mut delta_mouse_loc_y = get_delta(ctx).y;
+    fn replace_level(&mut self, level: Level, path: PathBuf) {
+        let mut lighting = level.lighting();
+        lighting.switch = self.lighting.switch;
+        lighting.smooth_switch = self.lighting.smooth_switch;
+        self.lighting = lighting;
+        self.level = level;
+        self.level_path = path;
+        self.editor.status.clear();
+        self.previous = (self.player.pos, self.player.jump);
+    }
 
-        set_mouse_location(ctx, Vector2::new(w * 0.5, h * 0.5)).unwrap();
+    fn quicksave(&mut self, ctx: &mut Context) -> GameResult {
+        SaveGame::capture(
+            &self.level_path,
+            &self.level,
+            &self.player,
+            self.time,
+            &self.torch,
+            RngState::capture(self.seed, &self.rng),
+        )
+        .save(ctx, Path::new(QUICKSAVE))
+    }
 
-        let mut angle_of_rot = 0.0f32;
-        if delta_mouse_loc_x != 0.0 {
-            delta_mouse_loc_x -= 240.0; // resolution width middle minus window width middle
-        }
+    /// Goes back to the quicksave, in whatever level it was made.
+    fn quickload(&mut self, ctx: &mut Context) -> GameResult {
+        let save = SaveGame::load(ctx, Path::new(QUICKSAVE))?;
+        let mut level = Level::load(ctx, &save.level)?;
+        save.restore(&mut level, &mut self.player, &self.renderer.settings())?;
+        self.time = save.time;
+        self.torch = save.torch.clone();
+        self.seed = save.rng.seed;
+        self.rng = save.rng.restore();
+        self.transition = None;
+        self.replace_level(level, save.level);
+        Ok(())
+    }
 
-        if delta_mouse_loc_y != 0.0 {
-            delta_mouse_loc_y -= 135.0;
+    /// Shows `done` if `result` is fine, else its error.
+    fn notify(&mut self, result: GameResult, done: &str) {
+        match result {
+            Ok(()) => self.show_notice(done),
+            Err(e) => self.show_notice(&e.to_string()),
         }
-        self.player.pitch -= delta_mouse_loc_y * 0.75;
-
-        self.player.pitch = clamp(self.player.pitch, -300.0, 300.0);
+    }
 
-        angle_of_rot += 0.085 * delta_mouse_loc_x;
-        self.player.plane = Vector2::rotate(self.player.plane, angle_of_rot.to_radians());
-        self.player.dir_norm = Vector2::rotate(self.player.dir_norm, angle_of_rot.to_radians());
+    fn show_notice(&mut self, message: &str) {
+        self.notice = Some((message.to_string(), self.time));
+    }
 
-        let mut dir = Vector2::new(0.0, 0.0);
-        let yoffset = 0.3125;
-        let mut check_pos_y = self.player.pos;
-        let mut check_pos_x = self.player.pos;
-        self.player.walking = false;
+    /// Records the input of every frame from now on, to be written to `path` when the game
+    /// quits.
+    pub fn record_demo(&mut self, path: PathBuf) {
+        self.demo = Some(DemoMode::Recording {
+            demo: Demo::new(&self.level_path, self.seed),
+            path,
+        });
+    }
 
-        if is_key_pressed(ctx, KeyCode::W) {
-            check_pos_y += Vector2::new(0.0, self.player.dir_norm.y.signum() * yoffset);
-            check_pos_x += Vector2::new(self.player.dir_norm.x.signum() * yoffset, 0.0);
-            dir += dir_norm;
-            self.player.walking = true;
-        }
-        if is_key_pressed(ctx, KeyCode::S) {
-            check_pos_y += Vector2::new(0.0, -self.player.dir_norm.y.signum() * yoffset);
-            check_pos_x += Vector2::new(-self.player.dir_norm.x.signum() * yoffset, 0.0);
-            dir -= dir_norm;
-            self.player.walking = true;
+    /// Plays `demo` back instead of reading the mouse and keyboard, from the start of the game.
+    pub fn play_demo(&mut self, demo: Demo) -> GameResult {
+        if demo.level != self.level_path {
+            return Err(GameError::ConfigError(format!(
+                "demo starts on {} but the game starts on {}",
+                demo.level.display(),
+                self.level_path.display()
+            )));
         }
+        self.seed = demo.seed;
+        self.rng = GameRng::seed_from_u64(demo.seed);
+        self.torch = Torch::new(&mut self.rng);
+        self.demo = Some(DemoMode::Playing { demo, next: 0 });
+        Ok(())
+    }
 
-        if is_key_pressed(ctx, KeyCode::A) {
-            check_pos_y += Vector2::new(0.0, -self.player.dir_norm.x.signum() * yoffset);
-            check_pos_x += Vector2::new(self.player.dir_norm.y.signum() * yoffset, 0.0);
-            dir += Vector2::new(dir_norm.y, -dir_norm.x);
-            self.player.walking = true;
+    /// Writes the demo being recorded, if any.
+    fn stop_recording(&mut self) {
+        if let Some(DemoMode::Recording { demo, path }) = self.demo.take() {
+            if let Err(e) = demo.save_file(&path) {
+                eprintln!("can't save the demo: {}", e);
+            }
         }
-        if is_key_pressed(ctx, KeyCode::D) {
-            check_pos_y += Vector2::new(0.0, self.player.dir_norm.x.signum() * yoffset);
-            check_pos_x += Vector2::new(-self.player.dir_norm.y.signum() * yoffset, 0.0);
-            dir += Vector2::new(-dir_norm.y, dir_norm.x);
-            self.player.walking = true;
+    }
+
+    /// Input of this frame, from the demo being played or else from the mouse and keyboard.
+    fn frame_input(&mut self, ctx: &mut Context) -> FrameInput {
+        let input = match &mut self.demo {
+            Some(demo @ DemoMode::Playing { .. }) => match demo.next_frame() {
+                Some(input) => return input,
+                None => {
+                    self.demo = None;
+                    self.show_notice("demo finished");
+                    FrameInput::read(ctx, &self.keymap)
+                }
+            },
+            _ if self.editor.active => FrameInput::idle(timer::delta(ctx).as_secs_f32()),
+            _ => FrameInput::read(ctx, &self.keymap),
+        };
+        if let Some(DemoMode::Recording { demo, .. }) = &mut self.demo {
+            demo.frames.push(input);
         }
+        input
+    }
 
-        if self.player.walking {
-            let cell_check_y = self.map.solid
-                [(check_pos_y.x) as usize + (check_pos_y.y) as usize * self.map_size.0];
-            let cell_check_x = self.map.solid
-                [(check_pos_x.x) as usize + (check_pos_x.y) as usize * self.map_size.0];
+    /// Changes the resolution, field of view or rays per pixel while the game is running.
+    pub fn set_render_settings(&mut self, settings: RenderSettings) {
+        self.renderer.set_settings(settings);
+        self.player.set_projection(&self.renderer.settings());
+    }
 
-            if cell_check_y {
-                dir.y = 0.0;
-            }
-            if cell_check_x {
-                dir.x = 0.0;
-            }
-            dir.normalize();
-            self.player.pos += dir * (2.5 * dt);
+    /// Paints or erases the cell under the mouse in editor mode.
+    fn edit(&mut self, ctx: &mut Context) {
+        let cell = match self.hovered_cell(ctx) {
+            Some(cell) => cell,
+            None => return,
+        };
+        let changed = if button_pressed(ctx, MouseButton::Left) {
+            self.editor.paint(&mut self.level, &self.player, cell)
+        } else if button_pressed(ctx, MouseButton::Right) {
+            self.editor.erase(&mut self.level, cell)
+        } else {
+            false
+        };
+        if changed {
+            let mut lighting = self.level.lighting();
+            lighting.switch = self.lighting.switch;
+            lighting.smooth_switch = self.lighting.smooth_switch;
+            self.lighting = lighting;
         }
+    }
 
-        if is_key_pressed(ctx, KeyCode::Space) {
-            let check_front = self.player.pos + self.player.dir_norm * 1.5;
-            let pos_door = (check_front.x) as usize + (check_front.y) as usize * self.map_size.0;
+    fn hovered_cell(&self, ctx: &mut Context) -> Option<(usize, usize)> {
+        self.editor.cell_at(
+            graphics::drawable_size(ctx),
+            self.level.map.size,
+            mouse_location(ctx),
+        )
+    }
 
-            if self.map.walls[pos_door] == 6 {
-                let door = self.map.doors.get_mut(&pos_door).expect("Cant find door");
-                if !door.opening {
-                    door.timer = self.time;
-                    door.opening = true;
-                }
-            }
-        }
+    /// Saves the level being edited in the assets as a level file, next to the one it was
+    /// loaded from.
+    fn save_level(&mut self) {
+        let path = Path::new(ASSETS_DIR)
+            .join(
+                self.level_path
+                    .strip_prefix("/")
+                    .unwrap_or(&self.level_path),
+            )
+            .with_extension("ron");
+        self.editor.status = match self.level.save_file(&path) {
+            Ok(()) => format!("saved to {}", path.display()),
+            Err(e) => format!("can't save: {}", e),
+        };
+    }
 
-        if is_key_pressed(ctx, KeyCode::LControl) {
-            if self.player.height > -300.0 {
-                self.player.height -= 30.0;
+    /// Simulates the game for one tick.
+    fn tick(&mut self, ctx: &mut Context, input: &FrameInput) -> GameResult {
+        self.previous = (self.player.pos, self.player.jump);
+        self.time += TICK;
+        // The player stays still while moving to another level or editing this one.
+        let playing = self.transition.is_none() && !self.editor.active;
+        if let Some(transition) = &mut self.transition {
+            if let Some(next) = transition.update(TICK) {
+                self.enter_level(ctx, next)?;
+            }
+            if matches!(&self.transition, Some(transition) if transition.finished()) {
+                self.transition = None;
             }
-        } else if self.player.height < 0.0 {
-            self.player.height += 30.0;
         }
-
-        if is_key_pressed(ctx, KeyCode::Q) {
-            self.player.jump += 2.0;
+        let events = simulate(
+            &mut self.level,
+            &mut self.player,
+            &mut self.torch,
+            &mut self.rng,
+            self.time,
+            playing.then_some(input),
+        );
+        if let Some(key) = events.locked {
+            self.show_notice(&format!("you need the {} key", key));
         }
-
-        if is_key_pressed(ctx, KeyCode::E) {
-            self.player.jump -= 2.0;
+        for key in events.picked_up {
+            self.show_notice(&format!("picked up the {} key", key));
         }
+        if let Some(exit) = events.exit {
+            let next = self.campaign.destination(&self.level_path, &exit);
+            self.transition = Some(Transition::new(next));
+        }
+        Ok(())
     }
 
-    pub fn calculate_ray(
-        &mut self,
-        ray_dir_player: Vector2<f32>,
-        theta: f32,
-        j: usize,
-    ) -> GameResult {
-        let ray_dir_norm = Vector2::rotate(ray_dir_player, theta);
-        let ray_unitstep_size = Vector2::new(
-            (1.0 + (ray_dir_norm.y / ray_dir_norm.x) * (ray_dir_norm.y / ray_dir_norm.x)).sqrt(),
-            (1.0 + (ray_dir_norm.x / ray_dir_norm.y) * (ray_dir_norm.x / ray_dir_norm.y)).sqrt(),
-        );
-        let startv = self.player.pos;
-
-        let mut map_checkv = Vector2::new(startv.x.floor(), startv.y.floor());
-        let mut ray_length1_d = Vector2::new(0.0f32, 0.0);
-        let mut orientation;
-        let mut stepv = Vector2::new(0.0f32, 0.0);
-        let mut last_was_door = false;
+    /// The player as drawn this frame, between where they were at the last two ticks.
+    fn view(&self) -> Player {
+        let alpha = self.timestep.alpha();
+        let (pos, jump) = self.previous;
+        let mut view = self.player.clone();
+        view.pos = pos + (self.player.pos - pos) * alpha;
+        view.jump = jump + (self.player.jump - jump) * alpha;
+        view
+    }
+}
 
-        if ray_dir_norm.x < 0.0 {
-            stepv.x = -1.0;
-            ray_length1_d.x = (startv.x - map_checkv.x) * ray_unitstep_size.x;
-        } else {
-            stepv.x = 1.0;
-            ray_length1_d.x = (map_checkv.x + 1.0 - startv.x) * ray_unitstep_size.x;
-        }
+/// What a tick of play did that the game reacts to, beyond moving the world on.
+#[derive(Debug, Default, PartialEq)]
+pub struct TickEvents {
+    /// Key missing to open the locked door the player tried to use.
+    pub locked: Option<Key>,
+    pub picked_up: Vec<Key>,
+    /// Exit the player stepped on, which leads to another level.
+    pub exit: Option<Exit>,
+}
 
-        if ray_dir_norm.y < 0.0 {
-            stepv.y = -1.0;
-            ray_length1_d.y = (startv.y - map_checkv.y) * ray_unitstep_size.y;
-        } else {
-            stepv.y = 1.0;
-            ray_length1_d.y = (map_checkv.y + 1.0 - startv.y) * ray_unitstep_size.y;
-        }
+/// Moves the game on by one tick to `time`, without needing a `ggez::Context`, so that demos
+/// can be replayed in tests exactly as the game plays them.
+///
+/// The player acts on `input`, or stands still without it, then sprites, doors, push-walls and
+/// the torch move on, the torch drawing its flicker from `rng`.
+pub fn simulate(
+    level: &mut Level,
+    player: &mut Player,
+    torch: &mut Torch,
+    rng: &mut GameRng,
+    time: f32,
+    input: Option<&FrameInput>,
+) -> TickEvents {
+    let mut events = TickEvents::default();
+    if let Some(input) = input {
+        events.locked = player.apply_input(input, &mut level.map, &level.sprites);
+        events.picked_up = player.pick_up_keys(&mut level.sprites);
+        events.exit = level.exit_at(player.pos).cloned();
+    }
 
-        let mut tilefound = false;
-        let mut distance = 0.0;
+    player.walk_animation(time);
 
-        while !tilefound && distance < 100.0 {
-            //arbitrary max distance
+    level
+        .sprites
+        .iter_mut()
+        .for_each(|sprite| sprite.update(time));
 
-            if ray_length1_d.x < ray_length1_d.y {
-                map_checkv.x += stepv.x;
-                distance = ray_length1_d.x;
-                ray_length1_d.x += ray_unitstep_size.x;
+    level.update_doors(time, player);
+    level.map.update_push_walls();
 
-                if ray_dir_norm.x < 0.0 {
-                    orientation = Orientation::W;
-                } else {
-                    orientation = Orientation::E;
-                }
-            } else {
-                map_checkv.y += stepv.y;
-                distance = ray_length1_d.y;
-                ray_length1_d.y += ray_unitstep_size.y;
+    torch.update_intensity(time, rng);
+    events
+}
 
-                if ray_dir_norm.y < 0.0 {
-                    orientation = Orientation::S;
-                } else {
-                    orientation = Orientation::N;
-                }
+impl EventHandler for MainState {
+    fn key_down_event(&mut self, ctx: &mut Context, keycode: KeyCode, keymods: KeyMods, _: bool) {
+        if self.editor.active {
+            if keycode == KeyCode::S && keymods.contains(KeyMods::CTRL) {
+                self.save_level();
+                return;
             }
-            if map_checkv.x >= 0.0
-                && map_checkv.x < self.map_size.0 as f32
-                && map_checkv.y >= 0.0
-                && map_checkv.y < self.map_size.1 as f32
-            {
-                let mut wall_type =
-                    self.map.walls[map_checkv.y as usize * self.map_size.0 + map_checkv.x as usize];
-
-                if last_was_door && wall_type > 0 {
-                    wall_type = 7;
-                }
-                last_was_door = false;
-                if wall_type == 6 {
-                    //door
-                    let door_offset = self
-                        .map
-                        .doors
-                        .get(&(map_checkv.y as usize * self.map_size.0 + map_checkv.x as usize))
-                        .expect("error finding door")
-                        .offset;
-
-                    tilefound = true;
-                    if orientation == Orientation::N || orientation == Orientation::S {
-                        if ray_length1_d.y - 0.5 * ray_unitstep_size.y < ray_length1_d.x {
-                            distance = ray_length1_d.y - ray_unitstep_size.y * 0.5;
-
-                            if door_offset < 1.0 {
-                                let pos_x = (startv.x + ray_dir_norm.x * distance) % 1.0;
-                                if pos_x > door_offset * 0.5 && 1.0 - pos_x > door_offset * 0.5 {
-                                    last_was_door = true;
-                                    tilefound = false;
-                                }
-                            }
-                        } else {
-                            // side wall
-                            if ray_dir_norm.x < 0.0 {
-                                orientation = Orientation::W;
-                                map_checkv.x -= 1.0;
-                            } else {
-                                orientation = Orientation::E;
-                                map_checkv.x += 1.0;
-                            }
-                            wall_type = 7;
-                            distance = ray_length1_d.x;
-                        }
-                    } else if orientation == Orientation::E || orientation == Orientation::W {
-                        if ray_length1_d.x - 0.5 * ray_unitstep_size.x <= ray_length1_d.y {
-                            distance = ray_length1_d.x - ray_unitstep_size.x * 0.5;
-                            if door_offset < 1.0 {
-                                let pos_y = (startv.y + ray_dir_norm.y * distance) % 1.0;
-                                if pos_y > door_offset * 0.5 && 1.0 - pos_y > door_offset * 0.5 {
-                                    last_was_door = true;
-                                    tilefound = false;
-                                }
-                            }
-                        } else {
-                            if ray_dir_norm.y < 0.0 {
-                                orientation = Orientation::S;
-                                map_checkv.y -= 1.0;
-                            } else {
-                                orientation = Orientation::N;
-                                map_checkv.y += 1.0;
-                            }
-                            wall_type = 7;
-                            distance = ray_length1_d.y;
-                        }
-                    }
-                } else if wall_type > 0 {
-                    tilefound = true;
-                }
-                if ((orientation == Orientation::W || orientation == Orientation::E)
-                    && self.map.walls
-                        [startv.y as usize * self.map_size.0 + (map_checkv.x - stepv.x) as usize]
-                        == 6)
-                    || ((orientation == Orientation::N || orientation == Orientation::S)
-                        && self.map.walls[(map_checkv.y - stepv.y) as usize * self.map_size.0
-                            + startv.x as usize]
-                            == 6)
-                {
-                    wall_type = 7;
-                }
-                if tilefound {
-                    let intersection = startv + ray_dir_norm * distance;
-                    self.intersections.points[j] = intersection.to_array();
-                    self.intersections.distance_fisheye[j] = distance;
-                    distance *= (theta).cos();
-
-                    self.intersections.distances[j] = distance;
-                    self.intersections.wall_type[j] = wall_type as usize;
-                    self.intersections.map_checkv[j] =
-                        map_checkv.y as usize * self.map_size.0 + map_checkv.x as usize;
-                    self.intersections.orientation[j] = orientation;
-                }
+            if self.editor.select_brush(keycode, &self.level.palette) {
+                return;
             }
         }
-        Ok(())
-    }
-
-    fn draw_slice(&self, slice: &mut [u8], j: usize, h: f32) {
-        let rect_h =
-            (self.player.planedist / (self.intersections.distances[j]) * 100.0).round() / 100.0;
-        let rect_top = (h - rect_h) * 0.5;
-        let rect_bottom = (h + rect_h) * 0.5;
-        let ty_step = (self.cell_size) / rect_h;
-        let pos = self.intersections.points[j];
-
-        let inter_x = pos[0] - pos[0].floor();
-        let inter_y = pos[1] - pos[1].floor();
-
-        let wall_type = self.intersections.wall_type[j];
-
-        let pos_z = self.player.jump / (self.intersections.distances[j]);
-        //draw walls
-        let mut ty = {
-            if rect_bottom - self.player.pitch - pos_z >= h {
-                (-self.player.pitch - pos_z - rect_top) * ty_step
-            } else if rect_top + self.player.pitch + pos_z < 0.0 {
-                (-self.player.pitch - pos_z - rect_bottom) * ty_step
-            } else {
-                0.0
-            }
+        let action = match self.keymap.action(keycode) {
+            Some(action) => action,
+            None => return,
         };
-
-        let mut tx;
-        match self.intersections.orientation[j] {
-            Orientation::N => {
-                tx = inter_x * self.cell_size;
-                tx = self.cell_size - 1.0 - tx.floor();
-            }
-            Orientation::E => {
-                tx = inter_y * self.cell_size;
+        match action {
+            Action::ToggleLighting => self.lighting.switch = !self.lighting.switch,
+            Action::ToggleSmoothLighting => {
+                self.lighting.smooth_switch = !self.lighting.smooth_switch
             }
-            Orientation::S => {
-                tx = inter_x * self.cell_size;
+            Action::ToggleEditor | Action::QuickLoad if self.demo.is_some() => {
+                self.show_notice("the editor and quickload are off while a demo runs");
             }
-            Orientation::W => {
-                tx = inter_y * self.cell_size;
-                tx = self.cell_size - 1.0 - tx.floor();
-            }
-        }
-        if wall_type == 6 {
-            let offset = 1.0
-                - self
-                    .map
-                    .doors
-                    .get(&self.intersections.map_checkv[j])
-                    .expect("error to draw door")
-                    .offset;
-            match self.intersections.orientation[j] {
-                Orientation::N => {
-                    if inter_x < 0.5 {
-                        tx -= offset * 64.0;
-                    } else {
-                        tx += offset * 64.0;
-                    }
-                }
-                Orientation::E => {
-                    if inter_y > 0.5 {
-                        tx -= offset * 64.0;
-                    } else {
-                        tx += offset * 64.0;
-                    }
-                }
-                Orientation::S => {
-                    if inter_x > 0.5 {
-                        tx -= offset * 64.0;
-                    } else {
-                        tx += offset * 64.0;
-                    }
-                }
-                Orientation::W => {
-                    if inter_y < 0.5 {
-                        tx -= offset * 64.0;
-                    } else {
-                        tx += offset * 64.0;
-                    }
+            Action::ToggleEditor => {
+                self.editor.active = !self.editor.active;
+                if self.editor.active {
+                    mouse_grabbed_and_hidden(ctx, false, false).unwrap();
+                } else {
+                    let (w, h) = graphics::drawable_size(ctx);
+                    set_mouse_location(ctx, Vector2::new(w * 0.5, h * 0.5)).unwrap();
                 }
             }
-        }
-
-        let rect_bottom_draw = {
-            if self.player.pitch + pos_z + rect_bottom >= h {
-                h - self.player.pitch - pos_z
-            } else {
-                rect_bottom
+            Action::QuickSave => {
+                let result = self.quicksave(ctx);
+                self.notify(result, "game saved");
             }
-        };
-
-        for y in (self.player.pitch + pos_z + rect_top) as usize
-            ..(self.player.pitch + pos_z + rect_bottom_draw) as usize
-        {
-            if ty >= 128.0 {
-                dbg!(
-                    rect_h,
-                    rect_top,
-                    rect_bottom,
-                    ty,
-                    self.player.pitch + pos_z + rect_top,
-                    self.player.pitch + pos_z + rect_bottom_draw,
-                    self.player.pitch
-                );
-                ty = 127.0;
+            Action::QuickLoad => {
+                let result = self.quickload(ctx);
+                self.notify(result, "game loaded");
             }
-
-            self.screen.draw_texture(
-                slice,
-                [tx as usize, wall_type * 128 + ty as usize],
-                y,
-                RAYSPERPIXEL,
-                self.torch.intensity
-                    * self.lighting.get_lighting_wall(
-                        tx / 128.0,
-                        ty * 0.0234375, //*3.0/128.0
-                        self.intersections.map_checkv[j],
-                        &self.intersections.orientation[j],
-                    ),
-                (3.0 / (self.intersections.distance_fisheye[j]
-                    * self.intersections.distance_fisheye[j]))
-                    .min(1.5),
-            );
-            ty += ty_step;
-        }
-
-        //draw floor
-        for y in (self.player.pitch + pos_z + rect_bottom) as usize..(h) as usize {
-            if !(j > 24 / RAYSPERPIXEL && j < 308 / RAYSPERPIXEL && y > 805) {
-                // Don't draw the floor behind the minimap image
-                let current_dist = self.buffer_floors[y]; // Use a buffer since they're always the same values
-                let weight = current_dist / (self.intersections.distances[j]);
-
-                let rhs = self.player.pos * (1.0 - weight);
-                let current_floor_x = weight * pos[0] + rhs.x;
-                let current_floor_y = weight * pos[1] + rhs.y;
-
-                let location =
-                    current_floor_x as usize + current_floor_y as usize * self.map_size.0;
-                let floor_type = self.map.floors[location];
-
-                let ftx = (current_floor_x * self.cell_size) as usize % 128;
-                let fty = (current_floor_y * self.cell_size) as usize % 128;
-                let lighting = self.lighting.get_lighting_floor(
-                    ftx as f32 / 128.0,
-                    fty as f32 / 128.0,
-                    location,
-                );
-                self.screen.draw_texture(
-                    slice,
-                    [ftx, (floor_type * 128) + fty],
-                    y,
-                    RAYSPERPIXEL,
-                    self.torch.intensity * lighting,
-                    (3.0 / (current_dist * current_dist)).min(1.5),
-                )
+            Action::Quit => {
+                self.stop_recording();
+                ggez::event::quit(ctx);
             }
-        }
-        //draw ceiling
-        let mut rect_top_draw = rect_top;
-        if rect_top + self.player.pitch + pos_z > h {
-            rect_top_draw = h - self.player.pitch - pos_z;
-        }
-        for y in 0..(rect_top_draw + self.player.pitch + pos_z) as usize {
-            let current_dist = self.buffer_floors[y];
-            let weight = current_dist / (self.intersections.distances[j]);
-
-            let rhs = self.player.pos * (1.0 - weight);
-            let current_floor_x = weight * pos[0] + rhs.x;
-            let current_floor_y = weight * pos[1] + rhs.y;
-
-            let ftx = (current_floor_x * self.cell_size) as usize % 128;
-            let fty = (current_floor_y * self.cell_size) as usize % 128;
-
-            self.screen.draw_texture(
-                slice,
-                [ftx, fty],
-                y,
-                RAYSPERPIXEL,
-                self.torch.intensity
-                    * self.lighting.get_lighting_floor(
-                        ftx as f32 / 128.0,
-                        fty as f32 / 128.0,
-                        current_floor_x as usize + current_floor_y as usize * self.map_size.0,
-                    ),
-                (3.0 / (current_dist * current_dist)).min(1.5),
-            );
-            //self.screen.draw_pixel(slice, y as usize, &[0, 0, 0, 0]);
-        }
-
-        self.sprites.iter().for_each(|sprite| {
-            sprite.draw(
-                slice,
-                &self.player,
-                j,
-                &self.screen,
-                self.intersections.distances[j],
-                RAYSPERPIXEL,
-            )
-        })
-    }
-}
-impl EventHandler for MainState {
-    fn key_down_event(&mut self, ctx: &mut Context, keycode: KeyCode, _: KeyMods, _: bool) {
-        match keycode {
-            KeyCode::L => self.lighting.switch = !self.lighting.switch,
-            KeyCode::K => self.lighting.smooth_switch = !self.lighting.smooth_switch,
-            KeyCode::Escape => ggez::event::quit(ctx),
+            // Moving, using and crouching are read every frame.
             _ => (),
         }
     }
-    fn update(&mut self, ctx: &mut Context) -> GameResult {
-        self.time += timer::delta(ctx).as_secs_f32();
-        self.handle_input(ctx, self.player.dir_norm);
-
-        self.player.walk_animation(&self.buffer_walking, self.time);
 
-        for j in 0..self.angles.len() {
-            self.calculate_ray(self.player.dir_norm, self.angles[j], j)?;
+    fn quit_event(&mut self, _: &mut Context) -> bool {
+        self.stop_recording();
+        false
+    }
+    fn resize_event(&mut self, ctx: &mut Context, width: f32, height: f32) {
+        if width < 1.0 || height < 1.0 {
+            // minimized
+            return;
         }
-        self.sprites
-            .iter_mut()
-            .for_each(|sprite| sprite.update(self.time));
-
-        self.map.doors.iter_mut().for_each(|(_, d)| {
-            if d.opening {
-                d.update(self.time, 0.01, &mut self.map.solid)
-            }
+        graphics::set_screen_coordinates(ctx, graphics::Rect::new(0.0, 0.0, width, height))
+            .unwrap();
+        self.set_render_settings(RenderSettings {
+            width: width as usize,
+            height: height as usize,
+            ..self.renderer.settings()
         });
+    }
 
-        self.torch.update_intensity(self.time);
-
+    fn update(&mut self, ctx: &mut Context) -> GameResult {
+        let input = self.frame_input(ctx);
+        if self.editor.active {
+            self.edit(ctx);
+        } else if self.transition.is_none() {
+            self.player.look(input.mouse);
+        }
+        for _ in 0..self.timestep.advance(input.dt) {
+            self.tick(ctx, &input)?;
+        }
         Ok(())
     }
 
     fn draw(&mut self, ctx: &mut Context) -> GameResult {
         let (w, h) = graphics::drawable_size(ctx);
         graphics::clear(ctx, Color::BLACK);
-        let mut corr_angle = self.player.dir_norm.angle();
+        let view = self.view();
+        let mut corr_angle = view.dir_norm.angle();
         if corr_angle < 0.0 {
             corr_angle += 2.0 * PI;
         }
         let draw_param = graphics::DrawParam {
             src: graphics::Rect::new(
-                360.0 / FOV * corr_angle / (2.0 * PI),
-                0.4 - self.player.pitch / 864.0,
+                360.0 / self.renderer.settings().fov * corr_angle / (2.0 * PI),
+                0.4 - view.pitch / 864.0,
                 1.0,
                 1.0,
             ),
@@ -659,38 +508,16 @@ impl EventHandler for MainState {
         self.sky.sb.set(self.sky.idx, draw_param)?;
         graphics::draw(ctx, &self.sky.sb, draw_param)?;
 
-        (0..h as usize).for_each(|y| {
-            if y < (self.player.pitch + h * 0.5) as usize {
-                // Calculate ceiling y buffer
-                self.buffer_floors[y] = (self.player.planedist - 2.0 * self.player.jump)
-                    / (-2.0 * (y as f32 - self.player.pitch) + h);
-            }
-            if y > (h * 0.5 + self.player.pitch) as usize {
-                // Calculate floor y buffer
-                self.buffer_floors[y] = (self.player.planedist + 2.0 * self.player.jump)
-                    / (2.0 * (-self.player.pitch + y as f32) - h);
-            }
-        });
-
-        self.sprites
-            .iter_mut()
-            .for_each(|sprite| sprite.set_drawing_bounds(ctx, &self.player, RAYSPERPIXEL as f32));
-
-        self.sprites.sort_by(|a: &Sprite, b: &Sprite| {
-            b.calculate_distance_2(&self.player)
-                .partial_cmp(&a.distance2)
-                .unwrap_or(std::cmp::Ordering::Equal)
-        });
-        let mut img_arr = std::mem::take(&mut self.screen.img_arr);
-
-        img_arr
-            .par_chunks_mut(h as usize * 4 * RAYSPERPIXEL)
-            .enumerate()
-            .for_each(|(j, slice)| self.draw_slice(slice, w as usize / RAYSPERPIXEL - j - 1, h));
-
-        self.screen.img_arr = img_arr;
+        self.renderer.render(
+            &self.level.map,
+            &view,
+            &mut self.level.sprites,
+            &self.lighting,
+            &self.torch,
+        );
 
-        let img = self.screen.arr_to_rgba(ctx)?;
+        let settings = self.renderer.settings();
+        let img = self.renderer.screen.arr_to_rgba(ctx)?;
 
         graphics::draw(
             ctx,
@@ -698,14 +525,50 @@ impl EventHandler for MainState {
             DrawParam::default()
                 .offset([0.5, 0.5])
                 .rotation(std::f32::consts::FRAC_PI_2)
+                .scale([h / settings.height as f32, w / settings.width as f32])
                 .dest([w * 0.5, h * 0.5]),
         )?;
 
         draw_fps_counter(ctx)?;
 
-        self.map.draw_minimap(ctx, self.map_size, &self.player)?;
+        self.minimap.draw(ctx, &self.level.map, &view)?;
 
-        self.player.draw_circle(ctx)?;
+        if self.editor.active {
+            let hovered = self.hovered_cell(ctx);
+            self.editor
+                .draw(ctx, &mut self.minimap, &self.level, &view, hovered)?;
+        }
+
+        if let Some((message, time)) = &self.notice {
+            if self.time - time < NOTICE_TIME {
+                let text = graphics::Text::new(message.as_str());
+                let (tw, th) = (text.width(ctx), text.height(ctx));
+                graphics::draw(
+                    ctx,
+                    &text,
+                    DrawParam::new().dest([(w - tw) * 0.5, h - th * 3.0]),
+                )?;
+            }
+        }
+
+        if let Some(transition) = &self.transition {
+            let screen = graphics::Mesh::new_rectangle(
+                ctx,
+                graphics::DrawMode::fill(),
+                graphics::Rect::new(0.0, 0.0, w, h),
+                Color::new(0.0, 0.0, 0.0, transition.fade),
+            )?;
+            graphics::draw(ctx, &screen, DrawParam::default())?;
+            if transition.loading() {
+                let text = graphics::Text::new("Loading...");
+                let (tw, th) = (text.width(ctx), text.height(ctx));
+                graphics::draw(
+                    ctx,
+                    &text,
+                    DrawParam::new().dest([(w - tw) * 0.5, (h - th) * 0.5]),
+                )?;
+            }
+        }
 
         graphics::present(ctx)
     }
@@ -725,28 +588,7 @@ pub fn draw_fps_counter(ctx: &mut Context) -> GameResult<()> {
     )
 }
 
-pub struct Intersections {
-    points: Vec<[f32; 2]>,
-    distances: Vec<f32>,
-    distance_fisheye: Vec<f32>,
-    map_checkv: Vec<usize>,
-    orientation: Vec<Orientation>,
-    wall_type: Vec<usize>,
-}
-
-impl Intersections {
-    pub fn new(w:usize) -> Self {
-        Self {
-            points: vec![[0.0, 0.0]; w/RAYSPERPIXEL],
-            distances: vec![0.0; w/RAYSPERPIXEL],
-            distance_fisheye: vec![0.0; w/RAYSPERPIXEL],
-            map_checkv: vec![0; w/RAYSPERPIXEL],
-            orientation: vec![Orientation::N; w/RAYSPERPIXEL],
-            wall_type: vec![0; w/RAYSPERPIXEL],
-        }
-    }
-}
-#[derive(PartialEq, Clone, Debug)]
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub enum Orientation {
     N = 1,
     E = 2,
@@ -1,4 +1,5 @@
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 
 use crate::Orientation;
 use std::collections::VecDeque;
@@ -201,7 +202,7 @@ pub fn lighting(torches_pos: Vec<usize>, map: &[bool], map_size: (usize, usize))
         if x > 0 {
             let neighbor = x - 1 + y * map_size.0;
             if !map[neighbor]
-                && light_int[neighbor] <= light_node - 2
+                && light_int[neighbor] + 2 <= light_node
                 && light_node != 0
                 && light_node != 1
             {
@@ -213,7 +214,7 @@ pub fn lighting(torches_pos: Vec<usize>, map: &[bool], map_size: (usize, usize))
         //Positive x neighbor
         if x < map_size.0 - 1 {
             let neighbor = x + 1 + y * map_size.0;
-            if !map[neighbor] && light_int[neighbor] <= light_node - 2 && light_node != 1 {
+            if !map[neighbor] && light_int[neighbor] + 2 <= light_node && light_node != 1 {
                 light_int[neighbor] = light_node - 1;
                 lightq.push_back(neighbor);
             }
@@ -222,7 +223,7 @@ pub fn lighting(torches_pos: Vec<usize>, map: &[bool], map_size: (usize, usize))
         //negative y neighbor
         if y > 0 {
             let neighbor = x + (y - 1) * map_size.0;
-            if !map[neighbor] && light_int[neighbor] <= light_node - 2 && light_node != 1 {
+            if !map[neighbor] && light_int[neighbor] + 2 <= light_node && light_node != 1 {
                 light_int[neighbor] = light_node - 1;
                 lightq.push_back(neighbor);
             }
@@ -231,7 +232,7 @@ pub fn lighting(torches_pos: Vec<usize>, map: &[bool], map_size: (usize, usize))
         //Positive y neighbor
         if y < map_size.1 - 1 {
             let neighbor = x + (y + 1) * map_size.0;
-            if !map[neighbor] && light_int[neighbor] <= light_node - 2 && light_node != 1 {
+            if !map[neighbor] && light_int[neighbor] + 2 <= light_node && light_node != 1 {
                 light_int[neighbor] = light_node - 1;
                 lightq.push_back(neighbor);
             }
@@ -328,6 +329,9 @@ impl Default for Vertex {
     }
 }
 
+/// Flickering light carried by the player. Saved games keep all of it, so that it goes on
+/// flickering the same way after loading.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Torch {
     pub intensity: f32,
     flicker_r_low: f32,
@@ -339,14 +343,45 @@ pub struct Torch {
 }
 
 impl Torch {
-    pub fn update_intensity(&mut self, timer: f32) {
+    /// A torch whose flicker is drawn from `rng`, so that a seeded generator makes it flicker
+    /// the same way every time.
+    pub fn new(rng: &mut impl Rng) -> Self {
+        let flicker_low_min = 200.0 / 256.0;
+        let flicker_low_max = 240.0 / 256.0;
+        let flicker_high_min = 230.0 / 256.0;
+        let flicker_high_max = 1.0;
+
+        Self {
+            flicker_r_low: rng.gen_range(flicker_low_min..flicker_low_max),
+            flicker_r_high: rng.gen_range(flicker_high_min..flicker_high_max),
+            flicker_r_hold: rng.gen_range(40.0..80.0),
+            flicker_r_pause: rng.gen_range(100.0..200.0),
+            state: 0,
+            intensity: rng.gen_range(flicker_low_min..flicker_low_max),
+            timer: 0.0,
+        }
+    }
+
+    /// A torch burning at a fixed intensity, for frames that must be reproducible.
+    pub fn with_intensity(intensity: f32) -> Self {
+        Self {
+            intensity,
+            flicker_r_low: intensity,
+            flicker_r_high: intensity,
+            flicker_r_hold: 0.0,
+            flicker_r_pause: 0.0,
+            state: 0,
+            timer: 0.0,
+        }
+    }
+
+    pub fn update_intensity(&mut self, timer: f32, rng: &mut impl Rng) {
         match self.state {
             0 => {
                 // low
                 if self.intensity > self.flicker_r_high {
                     self.state = 1;
-                    self.flicker_r_hold =
-                        rand::thread_rng().gen_range(80.0 / 1000.0..800.0 / 1000.0);
+                    self.flicker_r_hold = rng.gen_range(80.0 / 1000.0..800.0 / 1000.0);
                     self.timer = timer;
                 } else {
                     self.intensity += 0.01;
@@ -356,14 +391,14 @@ impl Torch {
                 // hold between low and high
                 if timer - self.timer > self.flicker_r_hold {
                     self.state = 2;
-                    self.flicker_r_low = rand::thread_rng().gen_range(220.0 / 256.0..240.0 / 256.0);
+                    self.flicker_r_low = rng.gen_range(220.0 / 256.0..240.0 / 256.0);
                 }
             }
             2 => {
                 //high
                 if self.intensity < self.flicker_r_low {
                     self.state = 3;
-                    self.flicker_r_pause = rand::thread_rng().gen_range(0.04..0.14);
+                    self.flicker_r_pause = rng.gen_range(0.04..0.14);
                     self.timer = timer;
                 } else {
                     self.intensity -= 0.015;
@@ -373,28 +408,55 @@ impl Torch {
                 //pause
                 if timer - self.timer > self.flicker_r_pause {
                     self.state = 0;
-                    self.flicker_r_high = rand::thread_rng().gen_range(230.0 / 256.0..1.0);
+                    self.flicker_r_high = rng.gen_range(230.0 / 256.0..1.0);
                 }
             }
         }
     }
 }
 
-impl Default for Torch {
-    fn default() -> Self {
-        let flicker_low_min = 200.0 / 256.0;
-        let flicker_low_max = 240.0 / 256.0;
-        let flicker_high_min = 230.0 / 256.0;
-        let flicker_high_max = 1.0;
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
 
-        Self {
-            flicker_r_low: rand::thread_rng().gen_range(flicker_low_min..flicker_low_max),
-            flicker_r_high: rand::thread_rng().gen_range(flicker_high_min..flicker_high_max),
-            flicker_r_hold: rand::thread_rng().gen_range(40.0..80.0),
-            flicker_r_pause: rand::thread_rng().gen_range(100.0..200.0),
-            state: 0,
-            intensity: rand::thread_rng().gen_range(flicker_low_min..flicker_low_max),
-            timer: 0.0,
-        }
+    use super::*;
+    use crate::map::Map;
+
+    #[test]
+    fn light_fades_with_distance_and_stops_at_walls() {
+        let (map, _) = Map::from_ascii(
+            "
+            #######
+            #...#.#
+            #######
+            ",
+        )
+        .unwrap();
+
+        let light = lighting(vec![1 + 7], &map.solid, map.size);
+
+        assert_eq!(light[1 + 7], 1.0);
+        assert_eq!(light[2 + 7], 0.75f32.powf(0.8));
+        assert_eq!(light[3 + 7], 0.75f32.powf(1.6));
+        // Nothing reaches the cell behind the wall.
+        assert_eq!(light[5 + 7], 0.75f32.powf(0.8 * 15.0));
+    }
+
+    #[test]
+    fn torches_flicker_the_same_with_the_same_seed() {
+        let flicker = |seed: u64| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut torch = Torch::new(&mut rng);
+            (1..600)
+                .map(|tick| {
+                    torch.update_intensity(tick as f32 / 60.0, &mut rng);
+                    torch.intensity.to_bits()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(flicker(7), flicker(7));
+        assert_ne!(flicker(7), flicker(8));
     }
 }
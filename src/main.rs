@@ -1,17 +1,120 @@
+use std::path::{Path, PathBuf};
+
 use ggez::conf::{WindowMode, WindowSetup};
-use ggez::{event, ContextBuilder, GameResult};
-use raycaster::MainState;
+use ggez::{event, ContextBuilder, GameError, GameResult};
+use raycaster::campaign::Campaign;
+use raycaster::controls::Keymap;
+use raycaster::demo::Demo;
+use raycaster::renderer::RenderSettings;
+use raycaster::{MainState, ASSETS_DIR};
 
 const TITLE: &str = "RayCaster";
+const CAMPAIGN: &str = "/campaign.ron";
 
 fn main() -> GameResult {
-    let window_mode = WindowMode::default().dimensions(1920.0, 1080.0);
+    let Options {
+        settings,
+        level,
+        record,
+        play,
+        seed,
+        keymap,
+    } = parse_args(std::env::args().skip(1))?;
+    let window_mode =
+        WindowMode::default().dimensions(settings.width as f32, settings.height as f32);
     let window_setup = WindowSetup::default().title(TITLE);
     let (mut ctx, events_loop) = ContextBuilder::new(TITLE, "migue")
         .window_mode(window_mode)
         .window_setup(window_setup)
-        .add_resource_path("assets")
+        .add_resource_path(ASSETS_DIR)
         .build()?;
-    let main_state = MainState::new(&mut ctx)?;
+    let campaign = match level {
+        Some(level) => Campaign::single(&level),
+        None => Campaign::load(&mut ctx, Path::new(CAMPAIGN))?,
+    };
+    let seed = seed.unwrap_or_else(rand::random);
+    let keymap = Keymap::load(&mut ctx, &keymap)?;
+    let mut main_state = MainState::new(&mut ctx, settings, campaign, seed, keymap)?;
+    if let Some(path) = play {
+        main_state.play_demo(Demo::load_file(&path)?)?;
+    } else if let Some(path) = record {
+        main_state.record_demo(path);
+    }
     event::run(ctx, events_loop, main_state)
 }
+
+struct Options {
+    settings: RenderSettings,
+    level: Option<PathBuf>,
+    /// File to record a demo of the game to.
+    record: Option<PathBuf>,
+    /// Demo to play back instead of reading the mouse and keyboard.
+    play: Option<PathBuf>,
+    /// Seed of the random numbers, a new one for every game when left out.
+    seed: Option<u64>,
+    /// Keys bound to each action, see `Keymap`.
+    keymap: PathBuf,
+}
+
+/// Reads `--level <path>`, `--size <w>x<h>`, `--fov <degrees>`, `--rays-per-pixel <n>`,
+/// `--record <demo>`, `--play <demo>`, `--seed <n>` and `--keymap <path>`.
+///
+/// The level path is looked up in the assets, e.g. `/level1.ron`. Without it the game plays the
+/// campaign in `/campaign.ron`. The keymap is looked up in the assets too, `/keymap.ron` by
+/// default. Demo paths are plain paths on disk.
+fn parse_args(mut args: impl Iterator<Item = String>) -> GameResult<Options> {
+    let mut options = Options {
+        settings: RenderSettings::default(),
+        level: None,
+        record: None,
+        play: None,
+        seed: None,
+        keymap: PathBuf::from("/keymap.ron"),
+    };
+    let settings = &mut options.settings;
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| GameError::ConfigError(format!("missing value for {}", arg)))?;
+        let invalid = || GameError::ConfigError(format!("invalid value '{}' for {}", value, arg));
+        match arg.as_str() {
+            "--level" => options.level = Some(PathBuf::from(value)),
+            "--record" => options.record = Some(PathBuf::from(value)),
+            "--play" => options.play = Some(PathBuf::from(value)),
+            "--keymap" => options.keymap = PathBuf::from(value),
+            "--seed" => options.seed = Some(value.parse().map_err(|_| invalid())?),
+            "--size" => {
+                let (w, h) = value.split_once('x').ok_or_else(invalid)?;
+                settings.width = w.parse().map_err(|_| invalid())?;
+                settings.height = h.parse().map_err(|_| invalid())?;
+                if settings.width == 0 || settings.height == 0 {
+                    return Err(GameError::ConfigError("size must be positive".to_string()));
+                }
+            }
+            "--fov" => {
+                settings.fov = value.parse().map_err(|_| invalid())?;
+                if !(settings.fov > 0.0 && settings.fov < 180.0) {
+                    return Err(GameError::ConfigError(
+                        "fov must be between 0 and 180 degrees".to_string(),
+                    ));
+                }
+            }
+            "--rays-per-pixel" => {
+                settings.rays_per_pixel = value.parse().map_err(|_| invalid())?;
+                if settings.rays_per_pixel == 0 {
+                    return Err(GameError::ConfigError(
+                        "rays per pixel must be at least 1".to_string(),
+                    ));
+                }
+            }
+            _ => return Err(GameError::ConfigError(format!("unknown option {}", arg))),
+        }
+    }
+    if settings.width < settings.rays_per_pixel {
+        return Err(GameError::ConfigError(format!(
+            "width {} is narrower than {} rays per pixel",
+            settings.width, settings.rays_per_pixel
+        )));
+    }
+    Ok(options)
+}
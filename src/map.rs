@@ -1,228 +1,1110 @@
-use std::{collections::HashMap, path::Path};
-
-use ggez::{
-    graphics::{self, DrawParam, Image, Rect},
-    Context, GameResult,
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
 };
 
-use crate::player::Player;
+use ggez::{graphics, Context, GameError, GameResult};
+use serde::{Deserialize, Serialize};
+
+use crate::collision::{overlaps_cell, SPRITE_RADIUS};
+use crate::movement::Surface;
+use crate::palette::{Palette, Tile, TEXTURES};
+use crate::sprite::Sprite;
+use crate::timestep::TICK;
+use crate::utilities::vector2::Vector2;
+
+/// Texture of the walls drawn with `#` in ASCII maps.
+const ASCII_WALL: usize = 2;
 
 pub struct Map {
     pub walls: Vec<usize>,
     pub floors: Vec<usize>,
+    pub ceilings: Vec<usize>,
     pub solid: Vec<bool>,
     pub doors: HashMap<usize, Door>,
-    pub minimap: Image,
-    pub sb: graphics::spritebatch::SpriteBatch,
+    /// Secret walls that slide back when used, by the cell they start in.
+    pub push_walls: HashMap<usize, PushWall>,
+    /// How the player moves over each floor texture, [`Surface::default`] for those left out.
+    pub surfaces: BTreeMap<usize, Surface>,
+    pub size: (usize, usize),
 }
 
 impl Map {
+    /// Loads the wall and floor layers through the ggez filesystem. The map is as big as the
+    /// wall layer, and the floor layer must have the same dimensions.
     pub fn new(
         ctx: &mut Context,
         path_walls: &Path,
         path_floors: &Path,
-        minimap: Image,
-        sb: graphics::spritebatch::SpriteBatch,
+        palette: &Palette,
+    ) -> GameResult<Self> {
+        let walls = graphics::Image::new(ctx, path_walls)?;
+        let floors = graphics::Image::new(ctx, path_floors)?;
+        let size = layers_size(
+            (walls.width() as usize, walls.height() as usize),
+            (floors.width() as usize, floors.height() as usize),
+        )?;
+        Self::from_rgba8(&walls.to_rgba8(ctx)?, &floors.to_rgba8(ctx)?, size, palette)
+    }
+
+    /// Same as [`Map::new`], reading the layers straight from disk.
+    pub fn load_file(path_walls: &Path, path_floors: &Path, palette: &Palette) -> GameResult<Self> {
+        let walls = image::open(path_walls)?.to_rgba8();
+        let floors = image::open(path_floors)?.to_rgba8();
+        let size = layers_size(
+            (walls.width() as usize, walls.height() as usize),
+            (floors.width() as usize, floors.height() as usize),
+        )?;
+        Self::from_rgba8(&walls.into_raw(), &floors.into_raw(), size, palette)
+    }
+
+    /// Builds a map from already decoded RGBA pixels of the wall and floor layers.
+    ///
+    /// Fails if a layer doesn't hold exactly `map_size` pixels, if it uses a color missing from
+    /// the palette or if the border isn't closed.
+    pub fn from_rgba8(
+        walls: &[u8],
+        floors: &[u8],
         map_size: (usize, usize),
+        palette: &Palette,
     ) -> GameResult<Self> {
-        let mut solid = vec![true; map_size.0 * map_size.1];
+        let (w, h) = map_size;
+        for (layer, pixels) in [("wall", walls), ("floor", floors)] {
+            if pixels.len() != w * h * 4 {
+                return Err(GameError::ResourceLoadError(format!(
+                    "{} layer has {} pixels, expected {} for a {}x{} map",
+                    layer,
+                    pixels.len() / 4,
+                    w * h,
+                    w,
+                    h
+                )));
+            }
+        }
+
+        palette.check()?;
+        check_colors("wall", walls, w, |color| palette.wall(color).is_some())?;
+        check_colors("floor", floors, w, |color| palette.floor(color).is_some())?;
+
+        Self::from_tiles(
+            &read_map_walls(walls, palette),
+            read_map_floors(floors, palette),
+            vec![0; w * h],
+            map_size,
+        )
+    }
+
+    /// Builds a map from its tiles and the floor and ceiling textures of every cell, in rows
+    /// from the top left corner. Fails if the border isn't closed.
+    pub fn from_tiles(
+        tiles: &[Tile],
+        floors: Vec<usize>,
+        ceilings: Vec<usize>,
+        map_size: (usize, usize),
+    ) -> GameResult<Self> {
+        let mut solid = vec![true; tiles.len()];
         let mut doors = HashMap::new();
-        Ok(Self {
-            walls: read_map_walls(ctx, path_walls, &mut solid, &mut doors)?,
-            floors: read_map_floors(ctx, path_floors)?,
+        let mut push_walls = HashMap::new();
+        let walls = tiles
+            .iter()
+            .enumerate()
+            .map(|(i, tile)| match tile {
+                Tile::Wall(texture) => *texture,
+                Tile::Door => {
+                    doors.insert(i, Door::new(i));
+                    6
+                }
+                Tile::PushWall(texture) => {
+                    push_walls.insert(i, PushWall::new(i, *texture));
+                    *texture
+                }
+                Tile::Empty => {
+                    solid[i] = false;
+                    0
+                }
+            })
+            .collect();
+        let map = Self {
+            walls,
+            floors,
+            ceilings,
             solid,
             doors,
-            minimap,
-            sb,
-        })
+            push_walls,
+            surfaces: BTreeMap::new(),
+            size: map_size,
+        };
+        map.check_border()?;
+        Ok(map)
     }
-    pub fn draw_minimap(
-        &mut self,
-        ctx: &mut Context,
-        map_size: (usize, usize),
-        player: &Player,
-    ) -> GameResult {
-        let (_w, h) = graphics::drawable_size(ctx);
-        graphics::draw(ctx, &self.minimap, DrawParam::default().dest([0.0, 780.0]))?;
-        let mut left = player.pos.x - 8.0;
-        if left < 0.0 {
-            left = 0.0;
-        }
-        let mut right = player.pos.x + 8.0;
-        if right > map_size.0 as f32 {
-            right = map_size.0 as f32;
-        }
-        let mut top = player.pos.y - 8.0;
-        if top < 0.0 {
-            top = 0.0;
-        }
-        let mut bottom = player.pos.y + 8.0;
-        if bottom > map_size.1 as f32 {
-            bottom = map_size.1 as f32;
-        }
-
-        let mut sprite_offset;
-        for i in left as usize..(right).ceil() as usize {
-            for j in top as usize..bottom.floor() as usize {
-                if self.walls[i + map_size.0 * j] > 0 {
-                    sprite_offset = 0.0;
-                } else {
-                    sprite_offset = 0.5;
-                }
 
-                if i == left as usize && j == top as usize {
-                    self.sb.add(get_drawparam(
-                        player,
-                        left,
-                        top,
-                        left % 1.0 * 0.5 + sprite_offset,
-                        top % 1.0,
-                        0.5 * (1.0 - left % 1.0),
-                        1.0 - top % 1.0,
-                    ));
-                } else if i == right as usize && j == top as usize {
-                    self.sb.add(get_drawparam(
-                        player,
-                        (right).floor(),
-                        top,
-                        sprite_offset,
-                        top % 1.0,
-                        0.5 * (right % 1.0),
-                        1.0 - top % 1.0,
-                    ));
-                } else if i == left as usize {
-                    self.sb.add(get_drawparam(
-                        player,
-                        left,
-                        j as f32,
-                        left % 1.0 * 0.5 + sprite_offset,
-                        0.0,
-                        0.5 * (1.0 - left % 1.0),
-                        1.0,
-                    ));
-                } else if i == (right) as usize {
-                    self.sb.add(get_drawparam(
-                        player,
-                        (right).floor(),
-                        j as f32,
-                        sprite_offset,
-                        0.0,
-                        0.5 * (right % 1.0),
-                        1.0,
-                    ));
-                } else if j == top as usize {
-                    self.sb.add(get_drawparam(
-                        player,
-                        i as f32,
-                        top,
-                        sprite_offset,
-                        top % 1.0,
-                        0.5,
-                        1.0 - top % 1.0,
-                    ));
-                } else {
-                    self.sb.add(get_drawparam(
-                        player,
-                        i as f32,
-                        j as f32,
-                        sprite_offset,
-                        0.0,
-                        0.5,
-                        1.0,
-                    ));
-                }
+    /// Tile the cell `i` is written back as. Push-walls stay secret until they are pushed, and
+    /// are then plain walls in the cell they stop in, or have most of themselves in while they
+    /// slide.
+    pub fn tile(&self, i: usize) -> Tile {
+        let width = self.size.0;
+        if self.doors.contains_key(&i) {
+            Tile::Door
+        } else if let Some(wall) = self.push_walls.get(&i).filter(|wall| wall.dir.is_none()) {
+            Tile::PushWall(wall.texture)
+        } else if let Some(wall) = self
+            .push_walls
+            .values()
+            .find(|wall| wall.moving() && wall.cell(width) == i)
+        {
+            Tile::Wall(wall.texture)
+        } else {
+            match self.walls[i] {
+                0 => Tile::Empty,
+                texture => Tile::Wall(texture),
             }
         }
-        graphics::draw(ctx, &self.sb, DrawParam::new().dest([0.0, h - 16.0 * 16.0]))?;
-        self.sb.clear();
+    }
+
+    /// Turns the map back into the RGBA pixels of its wall and floor layers, the inverse of
+    /// [`Map::from_rgba8`]. Ceilings aren't part of the layers and are left out.
+    pub fn to_rgba8(&self, palette: &Palette) -> GameResult<(Vec<u8>, Vec<u8>)> {
+        let cell = |i: usize| (i % self.size.0, i / self.size.0);
+        let mut walls = Vec::with_capacity(self.walls.len() * 4);
+        for i in 0..self.walls.len() {
+            let tile = self.tile(i);
+            let color = palette.wall_color(tile).ok_or_else(|| {
+                GameError::CustomError(format!(
+                    "palette has no color for {:?} at {:?}",
+                    tile,
+                    cell(i)
+                ))
+            })?;
+            walls.extend_from_slice(&color);
+        }
 
+        let mut floors = Vec::with_capacity(self.floors.len() * 4);
+        for (i, &texture) in self.floors.iter().enumerate() {
+            let color = palette.floor_color(texture).ok_or_else(|| {
+                GameError::CustomError(format!(
+                    "palette has no color for floor texture {} at {:?}",
+                    texture,
+                    cell(i)
+                ))
+            })?;
+            floors.extend_from_slice(&color);
+        }
+        Ok((walls, floors))
+    }
+
+    /// Writes the wall and floor layers to images that load back into the same map with the
+    /// same palette. The format follows the file extension.
+    pub fn save_file(
+        &self,
+        path_walls: &Path,
+        path_floors: &Path,
+        palette: &Palette,
+    ) -> GameResult {
+        let (walls, floors) = self.to_rgba8(palette)?;
+        let (w, h) = (self.size.0 as u32, self.size.1 as u32);
+        image::save_buffer(path_walls, &walls, w, h, image::ColorType::Rgba8)?;
+        image::save_buffer(path_floors, &floors, w, h, image::ColorType::Rgba8)?;
         Ok(())
     }
-}
-pub fn read_map_walls(
-    ctx: &mut Context,
-    path: &Path,
-    can_pass: &mut Vec<bool>,
-    door_offset: &mut HashMap<usize, Door>,
-) -> GameResult<Vec<usize>> {
-    let map = graphics::Image::new(ctx, path)?.to_rgba8(ctx)?;
-    let walls: Vec<usize> = map
-        .chunks(4)
-        .enumerate()
-        .map(|(i, color)| match color {
-            [255, 255, 0, 255] => 8,
-            [0, 0, 0, 255] => 2,
-            [0, 0, 255, 255] => 3,
-            [255, 0, 0, 255] => 4,
-            [0, 255, 0, 255] => 5,
-            [255, 0, 255, 255] => {
-                let door = Door::new(1.0, false, 0.0, i);
-                door_offset.insert(i, door);
-                6
+
+    /// Parses a map drawn as text, one character per cell:
+    ///
+    /// - `#` is a wall with the default texture and `1` to `9` a wall with that texture;
+    /// - `.` is an empty cell and `P` the empty cell the player starts in;
+    /// - `D` is a closed door and `S` a secret push-wall with the default texture.
+    ///
+    /// Whitespace around rows and blank lines are ignored, so that maps can be written inline in
+    /// indented string literals. Returns the map and the centre of the spawn cell, if any.
+    pub fn from_ascii(text: &str) -> GameResult<(Self, Option<Vector2<f32>>)> {
+        let rows: Vec<&str> = text
+            .lines()
+            .map(str::trim)
+            .filter(|row| !row.is_empty())
+            .collect();
+        let width = rows.first().map_or(0, |row| row.chars().count());
+        let mut tiles = Vec::with_capacity(width * rows.len());
+        let mut spawn = None;
+        for (y, row) in rows.iter().enumerate() {
+            if row.chars().count() != width {
+                return Err(ascii_error(format!(
+                    "row {} has {} cells, expected {}",
+                    y,
+                    row.chars().count(),
+                    width
+                )));
+            }
+            for (x, c) in row.chars().enumerate() {
+                tiles.push(match c {
+                    '#' => Tile::Wall(ASCII_WALL),
+                    '.' => Tile::Empty,
+                    'D' => Tile::Door,
+                    'S' => Tile::PushWall(ASCII_WALL),
+                    'P' if spawn.is_none() => {
+                        spawn = Some(Vector2::new(x as f32 + 0.5, y as f32 + 0.5));
+                        Tile::Empty
+                    }
+                    'P' => return Err(ascii_error(format!("second spawn at ({}, {})", x, y))),
+                    '6' => {
+                        return Err(ascii_error(format!(
+                            "texture 6 at ({}, {}) is reserved for doors, use D",
+                            x, y
+                        )))
+                    }
+                    '1'..='9' => Tile::Wall(c as usize - '0' as usize),
+                    _ => {
+                        return Err(ascii_error(format!(
+                            "unknown cell '{}' at ({}, {})",
+                            c, x, y
+                        )))
+                    }
+                });
+            }
+        }
+        if tiles.is_empty() {
+            return Err(ascii_error("map is empty"));
+        }
+
+        let map = Self::from_tiles(
+            &tiles,
+            vec![0; tiles.len()],
+            vec![0; tiles.len()],
+            (width, rows.len()),
+        )?;
+        Ok((map, spawn))
+    }
+
+    /// Writes the walls, doors and push-walls of the map in the format read by
+    /// [`Map::from_ascii`], with a `P` in the cell of `spawn`. Walls whose texture doesn't fit
+    /// in a digit are written as `#`, and push-walls as `S` whatever their texture.
+    pub fn to_ascii(&self, spawn: Option<Vector2<f32>>) -> String {
+        let spawn = spawn.map(|pos| pos.x as usize + pos.y as usize * self.size.0);
+        let mut text = String::with_capacity((self.size.0 + 1) * self.size.1);
+        for i in 0..self.walls.len() {
+            text.push(match self.tile(i) {
+                Tile::Door => 'D',
+                Tile::PushWall(_) => 'S',
+                Tile::Empty if spawn == Some(i) => 'P',
+                Tile::Empty => '.',
+                Tile::Wall(wall @ 1..=9) if wall != ASCII_WALL => (b'0' + wall as u8) as char,
+                Tile::Wall(_) => '#',
+            });
+            if i % self.size.0 == self.size.0 - 1 {
+                text.push('\n');
             }
-            _ => {
-                can_pass[i] = false;
-                0
+        }
+        text
+    }
+
+    /// Checks that the walls, floors and ceilings of every cell use textures of `wall128.png`,
+    /// so that a typo in a level fails to load rather than to render.
+    pub fn check_textures(&self) -> GameResult {
+        let layers = [
+            ("wall", &self.walls),
+            ("floor", &self.floors),
+            ("ceiling", &self.ceilings),
+        ];
+        for (layer, textures) in layers {
+            if let Some(i) = textures.iter().position(|&texture| texture >= TEXTURES) {
+                return Err(GameError::ResourceLoadError(format!(
+                    "{} at ({}, {}) uses texture {}, but there are only {}",
+                    layer,
+                    i % self.size.0,
+                    i / self.size.0,
+                    textures[i],
+                    TEXTURES
+                )));
             }
+        }
+        Ok(())
+    }
+
+    /// Checks that every cell on the edge of the map is a wall, so that no ray or player can
+    /// walk off the grid. Doors don't count since they open.
+    pub fn check_border(&self) -> GameResult {
+        let (w, h) = self.size;
+        let open: Vec<String> = (0..w * h)
+            .map(|i| (i % w, i / w))
+            .filter(|&(x, y)| x == 0 || y == 0 || x == w - 1 || y == h - 1)
+            .filter(|&(x, y)| matches!(self.walls[x + y * w], 0 | 6))
+            .map(|(x, y)| format!("({}, {})", x, y))
+            .collect();
+        if open.is_empty() {
+            Ok(())
+        } else {
+            Err(GameError::ResourceLoadError(format!(
+                "map border is open at {}",
+                open.join(", ")
+            )))
+        }
+    }
+
+    /// Surface of the floor under `pos`.
+    pub fn surface(&self, pos: Vector2<f32>) -> Surface {
+        let cell = pos.x as usize + pos.y as usize * self.size.0;
+        self.floors
+            .get(cell)
+            .and_then(|floor| self.surfaces.get(floor))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Height of the ceiling above the floor at `pos`, in cells. Every ceiling is as high as
+    /// the walls for now.
+    pub fn ceiling_height(&self, _pos: Vector2<f32>) -> f32 {
+        1.0
+    }
+
+    /// Turns the cell at `pos` into a closed door, returned to set its speed and hold time.
+    pub fn add_door(&mut self, pos: usize) -> &mut Door {
+        self.push_walls.remove(&pos);
+        self.walls[pos] = 6;
+        self.solid[pos] = true;
+        self.doors.insert(pos, Door::new(pos));
+        self.doors.get_mut(&pos).unwrap()
+    }
+
+    /// Moves every door on by a tick. A door doesn't close on any of `bodies`, circles given by
+    /// their centre and radius, standing in its doorway.
+    pub fn update_doors(&mut self, time: f32, bodies: &[(Vector2<f32>, f32)]) {
+        let width = self.size.0;
+        for door in self.doors.values_mut() {
+            let cell = (door.pos % width, door.pos / width);
+            let blocked = bodies
+                .iter()
+                .any(|&(pos, radius)| overlaps_cell(pos, radius, cell));
+            door.update(time, blocked, &mut self.solid);
+        }
+    }
+
+    /// Pushes the push-wall in `cell` away from `from`, along the axis it is furthest from it
+    /// on. The wall slides back by up to [`PUSH_DISTANCE`] cells, as far as the cells behind it
+    /// are empty and clear of solid `sprites`. Returns whether it started moving, which a
+    /// push-wall only does once.
+    pub fn push_wall(&mut self, cell: usize, from: Vector2<f32>, sprites: &[Sprite]) -> bool {
+        let (width, height) = (self.size.0 as isize, self.size.1 as isize);
+        let (x, y) = (cell as isize % width, cell as isize / width);
+        let away = Vector2::new(x as f32 + 0.5, y as f32 + 0.5) - from;
+        let dir = if away.x.abs() > away.y.abs() {
+            (away.x.signum() as isize, 0)
+        } else {
+            (0, away.y.signum() as isize)
+        };
+        let free = |k: isize| {
+            let (x, y) = (x + dir.0 * k, y + dir.1 * k);
+            (0..width).contains(&x)
+                && (0..height).contains(&y)
+                && self.walls[(x + y * width) as usize] == 0
+                && !self.solid[(x + y * width) as usize]
+                && !sprites.iter().any(|sprite| {
+                    sprite.solid()
+                        && overlaps_cell(sprite.pos, SPRITE_RADIUS, (x as usize, y as usize))
+                })
+        };
+        let travel = (1..=PUSH_DISTANCE as isize)
+            .take_while(|&k| free(k))
+            .count();
+        match self.push_walls.get_mut(&cell) {
+            Some(wall) if wall.dir.is_none() && travel > 0 => {
+                wall.dir = Some(dir);
+                wall.travel = travel;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Slides every moving push-wall on by a tick, making the cells it covers solid and those
+    /// it has left behind passable.
+    pub fn update_push_walls(&mut self) {
+        let width = self.size.0;
+        for wall in self.push_walls.values_mut().filter(|wall| wall.moving()) {
+            wall.moved = (wall.moved + PUSH_SPEED * TICK).min(wall.travel as f32);
+            wall.place(width, &mut self.walls, &mut self.solid);
+        }
+    }
+
+    /// The push-wall sliding through `cell`, if any. Cells a push-wall moves through are
+    /// solid but have no wall texture, which the wall is drawn with instead.
+    pub fn moving_push_wall(&self, cell: usize) -> Option<&PushWall> {
+        if self.walls[cell] != 0 || !self.solid[cell] {
+            return None;
+        }
+        let width = self.size.0;
+        self.push_walls.values().find(|wall| {
+            wall.moving()
+                && wall
+                    .path(width)
+                    .any(|(path, covered)| path == cell && covered)
         })
-        .collect();
+    }
+}
+
+fn ascii_error(message: impl std::fmt::Display) -> GameError {
+    GameError::ResourceLoadError(format!("ASCII map: {}", message))
+}
 
-    Ok(walls)
+fn layers_size(walls: (usize, usize), floors: (usize, usize)) -> GameResult<(usize, usize)> {
+    if walls == floors {
+        Ok(walls)
+    } else {
+        Err(GameError::ResourceLoadError(format!(
+            "floor layer is {}x{} but wall layer is {}x{}",
+            floors.0, floors.1, walls.0, walls.1
+        )))
+    }
 }
 
-pub fn read_map_floors(ctx: &mut Context, path: &Path) -> GameResult<Vec<usize>> {
-    let fmap = graphics::Image::new(ctx, path)?.to_rgba8(ctx)?;
-    let floors: Vec<usize> = fmap
-        .into_iter()
-        .step_by(4)
-        .map(|r| if r == 0 { 1 } else { 0 })
+/// Lists every pixel of a layer whose color `known` rejects, grouped by color.
+fn check_colors(
+    layer: &str,
+    pixels: &[u8],
+    width: usize,
+    known: impl Fn(&[u8]) -> bool,
+) -> GameResult {
+    let mut unmapped: Vec<(&[u8], Vec<String>)> = Vec::new();
+    for (i, color) in pixels.chunks(4).enumerate() {
+        if known(color) {
+            continue;
+        }
+        let cell = format!("({}, {})", i % width, i / width);
+        match unmapped.iter_mut().find(|(c, _)| *c == color) {
+            Some((_, cells)) => cells.push(cell),
+            None => unmapped.push((color, vec![cell])),
+        }
+    }
+    if unmapped.is_empty() {
+        return Ok(());
+    }
+    let colors: Vec<String> = unmapped
+        .iter()
+        .map(|(color, cells)| format!("{:?} at {}", color, cells.join(", ")))
         .collect();
-    Ok(floors)
+    Err(GameError::ResourceLoadError(format!(
+        "{} layer has colors missing from the palette: {}",
+        layer,
+        colors.join("; ")
+    )))
+}
+
+pub fn read_map_walls(map: &[u8], palette: &Palette) -> Vec<Tile> {
+    map.chunks(4)
+        .map(|color| palette.wall(color).unwrap_or(Tile::Empty))
+        .collect()
+}
+
+pub fn read_map_floors(fmap: &[u8], palette: &Palette) -> Vec<usize> {
+    fmap.chunks(4)
+        .map(|color| palette.floor(color).unwrap_or(0))
+        .collect()
+}
+
+/// Share of the doorway a door slides across each second, unless the level gives it another.
+pub const DOOR_SPEED: f32 = 0.6;
+/// Seconds a door stays open before closing by itself, unless the level gives it another.
+pub const DOOR_HOLD: f32 = 3.0;
+
+/// Colour of a key, and of the locked doors it opens.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Key {
+    Red,
+    Blue,
+    Yellow,
+}
+
+impl std::fmt::Display for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Key::Red => "red",
+            Key::Blue => "blue",
+            Key::Yellow => "yellow",
+        })
+    }
+}
+
+/// Cells a push-wall slides back by when the cells behind it are empty.
+pub const PUSH_DISTANCE: usize = 2;
+/// Cells per second a push-wall slides at.
+pub const PUSH_SPEED: f32 = 0.5;
+
+/// Secret wall that slides back, away from the player who used it, and stops once it has gone
+/// [`PushWall::travel`] cells.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PushWall {
+    /// Cell the wall starts in.
+    pub pos: usize,
+    pub texture: usize,
+    /// Cells the wall moves by along x and y, once pushed.
+    pub dir: Option<(isize, isize)>,
+    /// Cells the wall slides back by once pushed.
+    pub travel: usize,
+    /// Cells the wall has slid by so far.
+    pub moved: f32,
+}
+
+impl PushWall {
+    /// Push-wall that hasn't been pushed yet, in the cell `pos`.
+    pub fn new(pos: usize, texture: usize) -> Self {
+        Self {
+            pos,
+            texture,
+            dir: None,
+            travel: 0,
+            moved: 0.0,
+        }
+    }
+
+    /// Whether the wall has been pushed and is still sliding.
+    pub fn moving(&self) -> bool {
+        self.dir.is_some() && self.moved < self.travel as f32
+    }
+
+    /// Top left corner of the wall, a cell wide, on a map `width` cells wide.
+    pub fn corner(&self, width: usize) -> Vector2<f32> {
+        let (dx, dy) = self.dir.unwrap_or((0, 0));
+        Vector2::new(
+            (self.pos % width) as f32 + dx as f32 * self.moved,
+            (self.pos / width) as f32 + dy as f32 * self.moved,
+        )
+    }
+
+    /// Cell the wall is in, or the one it has most of itself in while sliding.
+    pub fn cell(&self, width: usize) -> usize {
+        let corner = self.corner(width);
+        (corner.x + 0.5) as usize + (corner.y + 0.5) as usize * width
+    }
+
+    /// Marks the cells the wall covers as solid in the `walls` and `solid` layers of a map
+    /// `width` cells wide, and those it has left behind as passable. A wall that isn't moving
+    /// rests in a single cell drawn like any other wall.
+    pub fn place(&self, width: usize, walls: &mut [usize], solid: &mut [bool]) {
+        for (cell, covered) in self.path(width) {
+            solid[cell] = covered;
+            walls[cell] = 0;
+        }
+        if !self.moving() {
+            let rest = self.cell(width);
+            walls[rest] = self.texture;
+            solid[rest] = true;
+        }
+    }
+
+    /// Cells from the one the wall starts in to the one it stops in, with whether the wall
+    /// covers part of each.
+    fn path(&self, width: usize) -> impl Iterator<Item = (usize, bool)> + '_ {
+        let (dx, dy) = self.dir.unwrap_or((0, 0));
+        let (x, y) = ((self.pos % width) as isize, (self.pos / width) as isize);
+        (0..=self.travel as isize).map(move |k| {
+            let cell = (x + dx * k) as usize + (y + dy * k) as usize * width;
+            (cell, (k as f32 - self.moved).abs() < 1.0)
+        })
+    }
 }
 
+/// How a door moves out of its doorway, each kind drawn with its own wall texture.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DoorKind {
+    /// Two halves that slide apart from the middle.
+    #[default]
+    Split,
+    /// A single panel that slides sideways into the wall.
+    Slide,
+    /// A portcullis that rises into the ceiling.
+    Lift,
+}
+
+impl DoorKind {
+    /// Row of the wall textures the door is drawn with.
+    pub fn texture(self) -> usize {
+        match self {
+            DoorKind::Split => 6,
+            DoorKind::Slide => 9,
+            DoorKind::Lift => 10,
+        }
+    }
+
+    /// Whether a ray crossing the middle of the doorway at `along`, from 0 to 1 across it,
+    /// misses a door covering `offset` of the doorway. Rays pass under a rising door as soon
+    /// as it starts rising, and draw what is left of it in front of what they hit next.
+    pub fn gap(self, along: f32, offset: f32) -> bool {
+        match self {
+            DoorKind::Split => along > offset * 0.5 && 1.0 - along > offset * 0.5,
+            DoorKind::Slide => along >= offset,
+            DoorKind::Lift => offset < 1.0,
+        }
+    }
+}
+
+/// Where a door is in its cycle. A door is solid in every state but [`DoorState::Open`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DoorState {
+    Closed,
+    Opening,
+    Open,
+    Closing,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Door {
+    /// Share of the doorway the door covers, from 1 when closed to 0 when open.
     pub offset: f32,
-    pub opening: bool,
+    pub state: DoorState,
+    /// Game time the door finished opening, which its hold time counts from.
     pub timer: f32,
     pub pos: usize,
+    /// Share of the doorway the door slides across each second.
+    pub speed: f32,
+    /// Seconds the door stays open before closing by itself, `None` to stay open until used.
+    pub hold: Option<f32>,
+    /// Key the player needs to open the door.
+    #[serde(default)]
+    pub lock: Option<Key>,
+    #[serde(default)]
+    pub kind: DoorKind,
 }
 
 impl Door {
-    pub fn new(offset: f32, opening: bool, timer: f32, pos: usize) -> Self {
+    /// Closed door in the cell `pos`, with the default speed and hold time.
+    pub fn new(pos: usize) -> Self {
         Self {
-            offset,
-            opening,
-            timer,
+            offset: 1.0,
+            state: DoorState::Closed,
+            timer: 0.0,
             pos,
+            speed: DOOR_SPEED,
+            hold: Some(DOOR_HOLD),
+            lock: None,
+            kind: DoorKind::Split,
         }
     }
 
-    pub fn update(&mut self, time: f32, trigger_time: f32, solid: &mut Vec<bool>) {
-        if time - self.timer > trigger_time {
-            self.timer = 0.0;
-            if self.offset > 0.001 {
-                self.offset -= 0.01;
-            } else {
-                self.opening = false;
-                solid[self.pos] = false;
+    /// Opens the door if it is closed or closing, closes it if it is open or opening.
+    pub fn toggle(&mut self) {
+        self.state = match self.state {
+            DoorState::Closed | DoorState::Closing => DoorState::Opening,
+            DoorState::Open | DoorState::Opening => DoorState::Closing,
+        };
+    }
+
+    /// Moves the door on by a tick. `time` is the game clock and `blocked` whether anything
+    /// stands in the doorway, in which case an open door stays open.
+    pub fn update(&mut self, time: f32, blocked: bool, solid: &mut [bool]) {
+        let step = self.speed * TICK;
+        match self.state {
+            DoorState::Closed => (),
+            DoorState::Opening => {
+                self.offset = (self.offset - step).max(0.0);
+                if self.offset <= 0.0 {
+                    self.state = DoorState::Open;
+                    self.timer = time;
+                    solid[self.pos] = false;
+                }
+            }
+            DoorState::Open => {
+                if matches!(self.hold, Some(hold) if time - self.timer >= hold) {
+                    self.state = DoorState::Closing;
+                    self.update(time, blocked, solid);
+                }
+            }
+            DoorState::Closing if !solid[self.pos] => {
+                // The door only starts closing once the doorway is clear, and is solid from
+                // then on so that nothing walks into it.
+                if blocked {
+                    self.state = DoorState::Open;
+                } else {
+                    solid[self.pos] = true;
+                    self.update(time, blocked, solid);
+                }
+            }
+            DoorState::Closing => {
+                self.offset = (self.offset + step).min(1.0);
+                if self.offset >= 1.0 {
+                    self.state = DoorState::Closed;
+                }
             }
         }
     }
 }
 
-fn get_drawparam(
-    player: &Player,
-    x_offset: f32,
-    y_offset: f32,
-    x_start: f32,
-    y_start: f32,
-    width: f32,
-    height: f32,
-) -> DrawParam {
-    DrawParam::default()
-        .dest([
-            16.0 * (10.0 - player.pos.x + x_offset),
-            16.0 * (9.0 - player.pos.y + y_offset),
-        ])
-        .src(Rect::new(x_start, y_start, width, height))
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sprite::SpriteType;
+
+    const WALL: [u8; 4] = [0, 0, 0, 255];
+    const EMPTY: [u8; 4] = [255, 255, 255, 255];
+
+    #[test]
+    fn infers_size_from_layers() {
+        let map = Map::load_file(
+            Path::new("assets/map.png"),
+            Path::new("assets/floor.png"),
+            &Palette::default(),
+        )
+        .unwrap();
+
+        assert_eq!(map.size, (16, 25));
+        assert_eq!(map.walls.len(), 16 * 25);
+        assert_eq!(map.floors.len(), 16 * 25);
+    }
+
+    #[test]
+    fn rejects_layers_of_different_sizes() {
+        let walls = [WALL; 9].concat();
+        let floors = [EMPTY; 6].concat();
+
+        match Map::from_rgba8(&walls, &floors, (3, 3), &Palette::default()) {
+            Err(GameError::ResourceLoadError(e)) => {
+                assert_eq!(e, "floor layer has 6 pixels, expected 9 for a 3x3 map")
+            }
+            _ => panic!("mismatched layers were accepted"),
+        }
+    }
+
+    #[test]
+    fn reports_open_border_cells() {
+        // 4x3 room with a gap on the right side and a door in the top wall.
+        let mut walls = [WALL; 12];
+        walls[5] = EMPTY;
+        walls[6] = EMPTY;
+        walls[7] = EMPTY;
+        walls[1] = [255, 0, 255, 255];
+        let floors = [EMPTY; 12].concat();
+
+        match Map::from_rgba8(&walls.concat(), &floors, (4, 3), &Palette::default()) {
+            Err(GameError::ResourceLoadError(e)) => {
+                assert_eq!(e, "map border is open at (1, 0), (3, 1)")
+            }
+            _ => panic!("open border was accepted"),
+        }
+    }
+
+    #[test]
+    fn reports_colors_missing_from_palette() {
+        let mut walls = [WALL; 9];
+        walls[4] = [12, 34, 56, 255];
+        let mut floors = [EMPTY; 9];
+        floors[4] = [1, 2, 3, 255];
+        floors[5] = [1, 2, 3, 255];
+        let mut palette = Palette::default();
+        palette.floors.insert([4, 5, 6, 255], 7);
+
+        match Map::from_rgba8(&walls.concat(), &floors.concat(), (3, 3), &palette) {
+            Err(GameError::ResourceLoadError(e)) => assert_eq!(
+                e,
+                "wall layer has colors missing from the palette: [12, 34, 56, 255] at (1, 1)"
+            ),
+            _ => panic!("unmapped color was accepted"),
+        }
+
+        walls[4] = [4, 5, 6, 255];
+        palette.walls.insert([4, 5, 6, 255], Tile::Wall(1));
+        match Map::from_rgba8(&walls.concat(), &floors.concat(), (3, 3), &palette) {
+            Err(GameError::ResourceLoadError(e)) => assert_eq!(
+                e,
+                "floor layer has colors missing from the palette: [1, 2, 3, 255] at (1, 1), (2, 1)"
+            ),
+            _ => panic!("unmapped color was accepted"),
+        }
+
+        floors[4] = [4, 5, 6, 255];
+        floors[5] = [4, 5, 6, 255];
+        let map = Map::from_rgba8(&walls.concat(), &floors.concat(), (3, 3), &palette).unwrap();
+        assert_eq!(map.walls[4], 1);
+        assert_eq!(map.floors[5], 7);
+    }
+
+    #[test]
+    fn ascii_round_trip() {
+        let text = "
+            #####
+            #P..3
+            #.DS#
+            ##9##
+        ";
+
+        let (map, spawn) = Map::from_ascii(text).unwrap();
+
+        assert_eq!(map.size, (5, 4));
+        assert_eq!(spawn, Some(Vector2::new(1.5, 1.5)));
+        assert_eq!(map.walls[4 + 5], 3);
+        assert_eq!(map.walls[2 + 3 * 5], 9);
+        assert!(map.doors.contains_key(&(2 + 2 * 5)));
+        assert_eq!(map.push_walls[&(3 + 2 * 5)].texture, ASCII_WALL);
+        assert!(!map.solid[3 + 5]);
+        assert_eq!(map.to_ascii(spawn), "#####\n#P..3\n#.DS#\n##9##\n");
+    }
+
+    #[test]
+    fn ascii_rejects_ragged_rows() {
+        match Map::from_ascii("####\n#..\n####") {
+            Err(GameError::ResourceLoadError(e)) => {
+                assert_eq!(e, "ASCII map: row 1 has 3 cells, expected 4")
+            }
+            _ => panic!("ragged rows were accepted"),
+        }
+    }
+
+    fn doorway() -> Map {
+        Map::from_ascii(
+            "
+            ###
+            #D#
+            #.#
+            ###
+            ",
+        )
+        .unwrap()
+        .0
+    }
+
+    /// Updates the doors of `map` tick after tick from `time` for `seconds`, with `bodies`
+    /// standing around, and returns the time reached.
+    fn run_doors(
+        map: &mut Map,
+        mut time: f32,
+        seconds: f32,
+        bodies: &[(Vector2<f32>, f32)],
+    ) -> f32 {
+        for _ in 0..(seconds / TICK).round() as usize {
+            time += TICK;
+            map.update_doors(time, bodies);
+        }
+        time
+    }
+
+    #[test]
+    fn door_opens_and_clears_its_cell() {
+        let mut map = doorway();
+        let door = map.doors.get_mut(&4).unwrap();
+        door.toggle();
+
+        let mut steps = 0;
+        while door.state == DoorState::Opening && steps < 1000 {
+            assert!(map.solid[4]);
+            door.update(0.0, false, &mut map.solid);
+            steps += 1;
+        }
+        assert_eq!(door.state, DoorState::Open);
+        assert_eq!(door.offset, 0.0);
+        assert!(!map.solid[4]);
+        assert!((100..=101).contains(&steps), "{}", steps);
+    }
+
+    #[test]
+    fn door_closes_once_its_hold_time_is_over() {
+        let mut map = doorway();
+        map.doors.get_mut(&4).unwrap().toggle();
+        let time = run_doors(&mut map, 0.0, 1.0 / DOOR_SPEED + 0.1, &[]);
+        assert_eq!(map.doors[&4].state, DoorState::Open);
+
+        let time = run_doors(&mut map, time, DOOR_HOLD - 0.2, &[]);
+        assert_eq!(map.doors[&4].state, DoorState::Open);
+        let time = run_doors(&mut map, time, 0.2, &[]);
+        assert_eq!(map.doors[&4].state, DoorState::Closing);
+        assert!(map.solid[4]);
+
+        run_doors(&mut map, time, 1.0 / DOOR_SPEED + 0.1, &[]);
+        assert_eq!(map.doors[&4].state, DoorState::Closed);
+        assert_eq!(map.doors[&4].offset, 1.0);
+    }
+
+    #[test]
+    fn door_stays_open_while_the_doorway_is_taken() {
+        let mut map = doorway();
+        let door = map.doors.get_mut(&4).unwrap();
+        door.hold = None;
+        door.toggle();
+        let time = run_doors(&mut map, 0.0, 2.0, &[]);
+        assert_eq!(map.doors[&4].state, DoorState::Open);
+
+        // A player standing across the threshold keeps the door from closing.
+        map.doors.get_mut(&4).unwrap().toggle();
+        let player = (Vector2::new(1.5, 2.2), 0.3);
+        let time = run_doors(&mut map, time, 1.0, &[player]);
+        assert_eq!(map.doors[&4].state, DoorState::Open);
+        assert!(!map.solid[4]);
+
+        map.doors.get_mut(&4).unwrap().toggle();
+        let time = run_doors(&mut map, time, 0.5, &[(Vector2::new(1.5, 2.5), 0.3)]);
+        assert_eq!(map.doors[&4].state, DoorState::Closing);
+
+        // Using it again while it closes opens it back.
+        map.doors.get_mut(&4).unwrap().toggle();
+        run_doors(&mut map, time, 2.0, &[]);
+        assert_eq!(map.doors[&4].state, DoorState::Open);
+    }
+
+    #[test]
+    fn saved_layers_load_back_into_the_same_map() {
+        let palette = Palette::default();
+        let map = Map::load_file(
+            Path::new("assets/map.png"),
+            Path::new("assets/floor.png"),
+            &palette,
+        )
+        .unwrap();
+        let dir = std::env::temp_dir();
+        let walls = dir.join(format!("raycaster-{}-walls.png", std::process::id()));
+        let floors = dir.join(format!("raycaster-{}-floors.png", std::process::id()));
+
+        map.save_file(&walls, &floors, &palette).unwrap();
+        let loaded = Map::load_file(&walls, &floors, &palette).unwrap();
+        std::fs::remove_file(walls).unwrap();
+        std::fs::remove_file(floors).unwrap();
+
+        assert_eq!(loaded.size, map.size);
+        assert_eq!(loaded.walls, map.walls);
+        assert_eq!(loaded.floors, map.floors);
+        assert_eq!(loaded.solid, map.solid);
+        let mut doors: Vec<_> = loaded.doors.keys().collect();
+        doors.sort();
+        let mut expected: Vec<_> = map.doors.keys().collect();
+        expected.sort();
+        assert_eq!(doors, expected);
+        assert_eq!(
+            map.to_rgba8(&palette).unwrap(),
+            (
+                image::open("assets/map.png").unwrap().to_rgba8().into_raw(),
+                image::open("assets/floor.png")
+                    .unwrap()
+                    .to_rgba8()
+                    .into_raw()
+            )
+        );
+    }
+
+    #[test]
+    fn export_needs_a_color_for_every_texture() {
+        let (map, _) = Map::from_ascii("###\n#7#\n###").unwrap();
+
+        match map.to_rgba8(&Palette::default()) {
+            Err(GameError::CustomError(e)) => {
+                assert_eq!(e, "palette has no color for Wall(7) at (1, 1)")
+            }
+            _ => panic!("texture missing from the palette was exported"),
+        }
+    }
+
+    /// Slides the push-walls of `map` tick after tick for `seconds`.
+    fn run_push_walls(map: &mut Map, seconds: f32) {
+        for _ in 0..(seconds / TICK).round() as usize {
+            map.update_push_walls();
+        }
+    }
+
+    #[test]
+    fn push_wall_slides_back_and_frees_its_cell() {
+        let (mut map, spawn) = Map::from_ascii(
+            "
+            #######
+            #P.S..#
+            #######
+            ",
+        )
+        .unwrap();
+        let player = spawn.unwrap();
+        assert_eq!(map.walls[3 + 7], ASCII_WALL);
+
+        assert!(map.push_wall(3 + 7, player, &[]));
+        run_push_walls(&mut map, 1.5);
+        let wall = &map.push_walls[&(3 + 7)];
+        assert!(wall.moving());
+        assert!((wall.corner(7).x - 3.75).abs() < 1e-3);
+        assert_eq!(
+            &map.solid[7..14],
+            [true, false, false, true, true, false, true]
+        );
+        assert_eq!(&map.walls[7..14], [2, 0, 0, 0, 0, 0, 2]);
+        assert!(map.moving_push_wall(4 + 7).is_some());
+
+        // It stops two cells back and can't be pushed again.
+        run_push_walls(&mut map, 5.0);
+        assert_eq!(
+            &map.solid[7..14],
+            [true, false, false, false, false, true, true]
+        );
+        assert_eq!(map.walls[5 + 7], ASCII_WALL);
+        assert!(map.moving_push_wall(5 + 7).is_none());
+        assert!(!map.push_wall(3 + 7, player, &[]));
+    }
+
+    #[test]
+    fn push_wall_only_slides_into_empty_cells() {
+        let (mut map, spawn) = Map::from_ascii(
+            "
+            #######
+            #P.S.D#
+            #.S####
+            #######
+            ",
+        )
+        .unwrap();
+        let player = spawn.unwrap();
+
+        // The door stops the first wall after a cell, the second is against the border.
+        assert!(map.push_wall(3 + 7, player, &[]));
+        assert_eq!(map.push_walls[&(3 + 7)].travel, 1);
+        assert!(!map.push_wall(2 + 2 * 7, player, &[]));
+        assert!(map.push_walls[&(2 + 2 * 7)].dir.is_none());
+    }
+
+    #[test]
+    fn push_wall_stops_at_solid_sprites() {
+        let travel = |sprites: &[Sprite]| {
+            let (mut map, spawn) = Map::from_ascii(
+                "
+                #######
+                #P.S..#
+                #######
+                ",
+            )
+            .unwrap();
+            map.push_wall(3 + 7, spawn.unwrap(), sprites);
+            map.push_walls[&(3 + 7)].travel
+        };
+
+        // Sprites that can be walked through don't stop it either.
+        assert_eq!(
+            travel(&[Sprite::new(SpriteType::Bat, Vector2::new(4.5, 1.5))]),
+            2
+        );
+        assert_eq!(
+            travel(&[Sprite::new(SpriteType::Armor, Vector2::new(5.5, 1.5))]),
+            1
+        );
+        assert_eq!(
+            travel(&[Sprite::new(SpriteType::Armor, Vector2::new(4.5, 1.5))]),
+            0
+        );
+    }
+
+    #[test]
+    fn pushed_walls_are_written_back_where_they_stopped() {
+        let (mut map, spawn) = Map::from_ascii(
+            "
+            ######
+            #P.S.#
+            #..S.#
+            ######
+            ",
+        )
+        .unwrap();
+        assert!(map.push_wall(3 + 6, spawn.unwrap(), &[]));
+        run_push_walls(&mut map, 5.0);
+
+        // The wall that was pushed is no secret anymore, the other one still is.
+        let text = map.to_ascii(spawn);
+        assert_eq!(text, "######\n#P..##\n#..S.#\n######\n");
+        let (reloaded, _) = Map::from_ascii(&text).unwrap();
+        assert_eq!(reloaded.push_walls.len(), 1);
+        assert_eq!(reloaded.walls, map.walls);
+        assert_eq!(reloaded.solid, map.solid);
+
+        let palette = Palette::default();
+        let (walls, floors) = map.to_rgba8(&palette).unwrap();
+        let reloaded = Map::from_rgba8(&walls, &floors, map.size, &palette).unwrap();
+        assert_eq!(reloaded.push_walls.len(), 1);
+        assert_eq!(reloaded.walls, map.walls);
+        assert_eq!(reloaded.solid, map.solid);
+    }
 }
@@ -0,0 +1,184 @@
+use ggez::{
+    graphics::{self, Color, DrawMode, DrawParam, Image, Mesh, MeshBuilder, Rect},
+    Context, GameResult,
+};
+
+use crate::{map::Map, player::Player};
+
+/// Top-down overlay of the map drawn with ggez on top of the rendered frame.
+pub struct Minimap {
+    pub image: Image,
+    pub sb: graphics::spritebatch::SpriteBatch,
+    pub mesh: Mesh,
+}
+
+impl Minimap {
+    pub fn new(
+        ctx: &mut Context,
+        image: Image,
+        sb: graphics::spritebatch::SpriteBatch,
+    ) -> GameResult<Self> {
+        let mesh = MeshBuilder::new()
+            .circle(
+                DrawMode::fill(),
+                [10.0 * 16.0, 9.0 * 16.0],
+                4.0,
+                0.1,
+                Color::new(145.0 / 255.0, 25.0 / 255.0, 16.0 / 255.0, 1.0),
+            )?
+            .build(ctx)?;
+        Ok(Self { image, sb, mesh })
+    }
+
+    pub fn draw(&mut self, ctx: &mut Context, map: &Map, player: &Player) -> GameResult {
+        let map_size = map.size;
+        let (_w, h) = graphics::drawable_size(ctx);
+        graphics::draw(
+            ctx,
+            &self.image,
+            DrawParam::default().dest([0.0, h - self.image.height() as f32]),
+        )?;
+        let mut left = player.pos.x - 8.0;
+        if left < 0.0 {
+            left = 0.0;
+        }
+        let mut right = player.pos.x + 8.0;
+        if right > map_size.0 as f32 {
+            right = map_size.0 as f32;
+        }
+        let mut top = player.pos.y - 8.0;
+        if top < 0.0 {
+            top = 0.0;
+        }
+        let mut bottom = player.pos.y + 8.0;
+        if bottom > map_size.1 as f32 {
+            bottom = map_size.1 as f32;
+        }
+
+        let mut sprite_offset;
+        for i in left as usize..(right).ceil() as usize {
+            for j in top as usize..bottom.floor() as usize {
+                if map.walls[i + map_size.0 * j] > 0 {
+                    sprite_offset = 0.0;
+                } else {
+                    sprite_offset = 0.5;
+                }
+
+                if i == left as usize && j == top as usize {
+                    self.sb.add(get_drawparam(
+                        player,
+                        left,
+                        top,
+                        left % 1.0 * 0.5 + sprite_offset,
+                        top % 1.0,
+                        0.5 * (1.0 - left % 1.0),
+                        1.0 - top % 1.0,
+                    ));
+                } else if i == right as usize && j == top as usize {
+                    self.sb.add(get_drawparam(
+                        player,
+                        (right).floor(),
+                        top,
+                        sprite_offset,
+                        top % 1.0,
+                        0.5 * (right % 1.0),
+                        1.0 - top % 1.0,
+                    ));
+                } else if i == left as usize {
+                    self.sb.add(get_drawparam(
+                        player,
+                        left,
+                        j as f32,
+                        left % 1.0 * 0.5 + sprite_offset,
+                        0.0,
+                        0.5 * (1.0 - left % 1.0),
+                        1.0,
+                    ));
+                } else if i == (right) as usize {
+                    self.sb.add(get_drawparam(
+                        player,
+                        (right).floor(),
+                        j as f32,
+                        sprite_offset,
+                        0.0,
+                        0.5 * (right % 1.0),
+                        1.0,
+                    ));
+                } else if j == top as usize {
+                    self.sb.add(get_drawparam(
+                        player,
+                        i as f32,
+                        top,
+                        sprite_offset,
+                        top % 1.0,
+                        0.5,
+                        1.0 - top % 1.0,
+                    ));
+                } else {
+                    self.sb.add(get_drawparam(
+                        player,
+                        i as f32,
+                        j as f32,
+                        sprite_offset,
+                        0.0,
+                        0.5,
+                        1.0,
+                    ));
+                }
+            }
+        }
+        graphics::draw(ctx, &self.sb, DrawParam::new().dest([0.0, h - 16.0 * 16.0]))?;
+        self.sb.clear();
+
+        graphics::draw(
+            ctx,
+            &self.mesh,
+            DrawParam::new().dest([0.0, h - 16.0 * 16.0]),
+        )?;
+        Ok(())
+    }
+
+    /// Draws every cell of the map with the minimap tiles, `cell_size` pixels wide, with the top
+    /// left corner of the map at `dest`.
+    pub fn draw_grid(
+        &mut self,
+        ctx: &mut Context,
+        map: &Map,
+        dest: [f32; 2],
+        cell_size: f32,
+    ) -> GameResult {
+        let scale = cell_size / 16.0;
+        for (i, &wall) in map.walls.iter().enumerate() {
+            let sprite_offset = if wall > 0 { 0.0 } else { 0.5 };
+            self.sb.add(
+                DrawParam::default()
+                    .src(Rect::new(sprite_offset, 0.0, 0.5, 1.0))
+                    .dest([
+                        (i % map.size.0) as f32 * cell_size,
+                        (i / map.size.0) as f32 * cell_size,
+                    ])
+                    .scale([scale, scale]),
+            );
+        }
+        graphics::draw(ctx, &self.sb, DrawParam::new().dest(dest))?;
+        self.sb.clear();
+        Ok(())
+    }
+}
+
+fn get_drawparam(
+    player: &Player,
+    x_offset: f32,
+    y_offset: f32,
+    x_start: f32,
+    y_start: f32,
+    width: f32,
+    height: f32,
+) -> DrawParam {
+    DrawParam::default()
+        .dest([
+            16.0 * (10.0 - player.pos.x + x_offset),
+            16.0 * (9.0 - player.pos.y + y_offset),
+        ])
+        .src(Rect::new(x_start, y_start, width, height))
+}
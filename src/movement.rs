@@ -0,0 +1,104 @@
+//! How the player gathers speed and slows down on each kind of floor.
+use serde::{Deserialize, Serialize};
+
+use crate::timestep::TICK;
+use crate::utilities::vector2::Vector2;
+
+/// How the player moves over a floor texture.
+///
+/// Level files list the floors that differ from the default by texture, e.g. an icy floor with
+/// `surfaces: { 1: (acceleration: 3.0, friction: 1.0) }`. Fields left out keep their default.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Surface {
+    /// Top walking speed, in cells per second.
+    pub max_speed: f32,
+    /// How many times faster than walking the player runs while sprinting.
+    pub sprint: f32,
+    /// Speed gained each second towards where the player walks, in cells per second.
+    pub acceleration: f32,
+    /// Speed lost each second once the player lets go, in cells per second.
+    pub friction: f32,
+}
+
+impl Default for Surface {
+    fn default() -> Self {
+        Self {
+            max_speed: 2.5,
+            sprint: 1.6,
+            acceleration: 25.0,
+            friction: 20.0,
+        }
+    }
+}
+
+impl Surface {
+    /// Velocity after one tick of walking towards `wish`, whose length from 0 to 1 is the share
+    /// of the top speed asked for.
+    pub fn accelerate(
+        &self,
+        velocity: Vector2<f32>,
+        wish: Vector2<f32>,
+        sprinting: bool,
+    ) -> Vector2<f32> {
+        let top = if sprinting {
+            self.max_speed * self.sprint
+        } else {
+            self.max_speed
+        };
+        let rate = if wish.magnitude() > 0.0 {
+            self.acceleration
+        } else {
+            self.friction
+        };
+        let mut change = wish * top - velocity;
+        change.limit(rate * TICK);
+        velocity + change
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Velocity after walking towards `wish` for `seconds`, starting from `velocity`.
+    fn walk(
+        surface: &Surface,
+        mut velocity: Vector2<f32>,
+        wish: Vector2<f32>,
+        sprinting: bool,
+        seconds: f32,
+    ) -> Vector2<f32> {
+        for _ in 0..(seconds / TICK).round() as usize {
+            velocity = surface.accelerate(velocity, wish, sprinting);
+        }
+        velocity
+    }
+
+    #[test]
+    fn speeds_up_to_the_top_speed() {
+        let surface = Surface::default();
+        let forward = Vector2::new(1.0, 0.0);
+        let still = Vector2::new(0.0, 0.0);
+
+        let started = walk(&surface, still, forward, false, 0.05);
+        assert!(started.x > 0.0 && started.x < surface.max_speed);
+        assert_eq!(walk(&surface, still, forward, false, 1.0).x, 2.5);
+        assert_eq!(walk(&surface, still, forward, true, 1.0).x, 4.0);
+        assert_eq!(walk(&surface, still, forward * 0.5, false, 1.0).x, 1.25);
+    }
+
+    #[test]
+    fn slides_longer_on_slippery_floors() {
+        let ice = Surface {
+            friction: 1.0,
+            ..Default::default()
+        };
+        let moving = Vector2::new(2.5, 0.0);
+        let idle = Vector2::new(0.0, 0.0);
+
+        assert_eq!(walk(&Surface::default(), moving, idle, false, 0.5), idle);
+        assert!(walk(&ice, moving, idle, false, 0.5).x > 1.5);
+        assert_eq!(walk(&ice, moving, idle, false, 3.0), idle);
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::BTreeMap;
+
+use ggez::{GameError, GameResult};
+use serde::{Deserialize, Serialize};
+
+/// Color of a pixel in a map layer.
+pub type Rgba = [u8; 4];
+
+/// Wall texture used for empty cells, never drawn.
+const EMPTY: usize = 0;
+/// Wall texture drawn on doors.
+const DOOR: usize = 6;
+/// Number of textures in `wall128.png`, one per 128 pixel row, which walls, floors and ceilings
+/// are drawn with.
+pub const TEXTURES: usize = 11;
+
+/// What a color of the wall layer turns into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Tile {
+    Empty,
+    /// Solid wall drawn with the given texture, counted in 128 pixel rows of `wall128.png`.
+    Wall(usize),
+    Door,
+    /// Secret wall drawn with the given texture, that slides back when the player uses it.
+    PushWall(usize),
+}
+
+/// Maps the colors of the wall and floor layers to tiles and floor textures.
+///
+/// In a level file it reads like
+/// `(walls: { (0, 0, 0, 255): Wall(2), (255, 255, 255, 255): Empty }, floors: { ... })`.
+/// A list that is left out keeps its default mapping, a list that is given replaces it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Palette {
+    pub walls: BTreeMap<Rgba, Tile>,
+    /// Floor textures, counted in 128 pixel rows of `wall128.png`.
+    pub floors: BTreeMap<Rgba, usize>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            walls: BTreeMap::from([
+                ([255, 255, 255, 255], Tile::Empty),
+                ([0, 0, 0, 255], Tile::Wall(2)),
+                ([0, 0, 255, 255], Tile::Wall(3)),
+                ([255, 0, 0, 255], Tile::Wall(4)),
+                ([0, 255, 0, 255], Tile::Wall(5)),
+                ([255, 0, 255, 255], Tile::Door),
+                ([255, 255, 0, 255], Tile::Wall(8)),
+                ([128, 128, 128, 255], Tile::PushWall(2)),
+            ]),
+            floors: BTreeMap::from([([255, 255, 255, 255], 0), ([0, 0, 0, 255], 1)]),
+        }
+    }
+}
+
+impl Palette {
+    pub fn wall(&self, color: &[u8]) -> Option<Tile> {
+        self.walls.get(color).copied()
+    }
+
+    pub fn floor(&self, color: &[u8]) -> Option<usize> {
+        self.floors.get(color).copied()
+    }
+
+    /// First color that maps to `tile`, used to write maps back to images.
+    pub fn wall_color(&self, tile: Tile) -> Option<Rgba> {
+        self.walls
+            .iter()
+            .find(|(_, t)| **t == tile)
+            .map(|(color, _)| *color)
+    }
+
+    /// First color that maps to the floor `texture`.
+    pub fn floor_color(&self, texture: usize) -> Option<Rgba> {
+        self.floors
+            .iter()
+            .find(|(_, t)| **t == texture)
+            .map(|(color, _)| *color)
+    }
+
+    /// Rejects wall textures that the renderer gives a special meaning to, and textures
+    /// missing from `wall128.png`.
+    pub fn check(&self) -> GameResult {
+        let textures = self.walls.iter().filter_map(|(color, tile)| match *tile {
+            Tile::Wall(texture) | Tile::PushWall(texture) => Some((color, texture)),
+            Tile::Empty | Tile::Door => None,
+        });
+        if let Some((color, texture)) = textures
+            .chain(self.floors.iter().map(|(color, &texture)| (color, texture)))
+            .find(|&(_, texture)| texture >= TEXTURES)
+        {
+            return Err(GameError::ResourceLoadError(format!(
+                "color {:?} uses texture {}, but there are only {}",
+                color, texture, TEXTURES
+            )));
+        }
+        for (color, tile) in &self.walls {
+            let (texture, reserved) = match *tile {
+                Tile::Wall(EMPTY) | Tile::PushWall(EMPTY) => (EMPTY, "empty cells"),
+                Tile::Wall(DOOR) | Tile::PushWall(DOOR) => (DOOR, "doors"),
+                _ => continue,
+            };
+            return Err(GameError::ResourceLoadError(format!(
+                "color {:?} uses wall texture {}, which is reserved for {}",
+                color, texture, reserved
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_partial_palette() {
+        let palette: Palette = ron::from_str("(walls: { (10, 20, 30, 255): Wall(1) })").unwrap();
+
+        assert_eq!(palette.wall(&[10, 20, 30, 255]), Some(Tile::Wall(1)));
+        assert_eq!(palette.wall(&[0, 0, 0, 255]), None);
+        assert_eq!(palette.floor(&[0, 0, 0, 255]), Some(1));
+    }
+
+    #[test]
+    fn rejects_reserved_textures() {
+        let palette: Palette = ron::from_str("(walls: { (10, 20, 30, 255): Wall(6) })").unwrap();
+
+        match palette.check() {
+            Err(GameError::ResourceLoadError(e)) => assert!(e.contains("reserved for doors")),
+            _ => panic!("door texture was accepted as a wall"),
+        }
+    }
+
+    #[test]
+    fn rejects_missing_textures() {
+        let palette: Palette = ron::from_str("(floors: { (10, 20, 30, 255): 11 })").unwrap();
+
+        match palette.check() {
+            Err(GameError::ResourceLoadError(e)) => assert!(e.contains("only 11"), "{}", e),
+            _ => panic!("missing texture was accepted"),
+        }
+    }
+
+    #[test]
+    fn counts_the_textures_of_the_atlas() {
+        let atlas = image::open("assets/wall128.png").unwrap();
+
+        assert_eq!(atlas.height() as usize, TEXTURES * 128);
+    }
+}
@@ -1,65 +1,374 @@
-use ggez::{
-    graphics::{self, Color, DrawMode, DrawParam, Mesh, MeshBuilder},
-    Context, GameResult,
-};
+use std::collections::BTreeSet;
 
-use crate::utilities::vector2::Vector2;
+use num::clamp;
 
+use crate::collision::{move_with_collision, PLAYER_RADIUS};
+use crate::controls::FrameInput;
+use crate::map::{Key, Map};
+use crate::sprite::Sprite;
+use crate::timestep::TICK;
+use crate::{renderer::RenderSettings, utilities::vector2::Vector2};
+
+/// Height of the eyes above the feet when standing, in cells. The camera sits halfway up the
+/// walls.
+pub const EYE_HEIGHT: f32 = 0.5;
+/// Height of the eyes above the feet when crouching.
+pub const CROUCH_EYE_HEIGHT: f32 = 0.37;
+/// Room the head takes up above the eyes, which has to fit under the ceiling.
+const HEAD_ROOM: f32 = 0.1;
+/// Cells per second the eyes go down while crouching and up while standing.
+const CROUCH_SPEED: f32 = 0.8;
+/// Cells per second added upwards by a jump.
+const JUMP_SPEED: f32 = 2.2;
+/// Cells per second taken from the upwards velocity every second.
+const GRAVITY: f32 = 9.0;
+/// How close the player has to come to a key to pick it up, added to their radius.
+const KEY_REACH: f32 = 0.2;
+/// How far in front of the player doors and push-walls can be used, in cells.
+const USE_REACH: f32 = 1.5;
+/// Length of the steps taken along the view to find what is used, in cells.
+const USE_STEP: f32 = 1.0 / 32.0;
+
+#[derive(Clone)]
 pub struct Player {
     pub pos: Vector2<f32>,
+    /// Cells per second the player is moving at.
+    pub velocity: Vector2<f32>,
     pub dir_norm: Vector2<f32>,
     pub plane: Vector2<f32>,
     pub planedist: f32,
     pub pitch: f32,
+    /// How far the camera is moved up from halfway up the walls, in pixels at a distance of
+    /// one cell. Worked out from the vertical state below on every tick.
     pub jump: f32,
     pub walking: bool,
+    /// Height of the eyes above the feet, in cells, from [`CROUCH_EYE_HEIGHT`] to
+    /// [`EYE_HEIGHT`].
     pub height: f32,
-    pub mesh: Mesh,
+    /// Height of the feet above the floor, in cells.
+    pub z: f32,
+    /// Cells per second the player is moving up at.
+    pub vertical_velocity: f32,
+    /// Whether use was held at the previous tick, so that holding it uses a door only once.
+    pub using: bool,
+    /// Keys picked up, which open the doors locked with them.
+    pub keys: BTreeSet<Key>,
+    /// Radius of the circle the player takes up when bumping into walls and sprites.
+    pub radius: f32,
 }
 
 impl Player {
     pub fn new(
-        ctx: &mut Context,
         pos: Vector2<f32>,
         dir_norm: Vector2<f32>,
         plane: Vector2<f32>,
         planedist: f32,
         pitch: f32,
         jump: f32,
-    ) -> GameResult<Self> {
-        let (_w, h) = graphics::drawable_size(ctx);
-        let mesh = MeshBuilder::new()
-            .circle(
-                DrawMode::fill(),
-                [10.0 * 16.0, h - 7.0 * 16.0],
-                4.0,
-                0.1,
-                Color::new(145.0 / 255.0, 25.0 / 255.0, 16.0 / 255.0, 1.0),
-            )?
-            .build(ctx)?;
-        Ok(Self {
+    ) -> Self {
+        Self {
             pos,
+            velocity: Vector2::new(0.0, 0.0),
             dir_norm,
             plane,
             planedist,
             pitch,
-            mesh,
             jump,
-            height: 0.0,
+            height: EYE_HEIGHT,
+            z: 0.0,
+            vertical_velocity: 0.0,
+            using: false,
+            keys: BTreeSet::new(),
             walking: false,
-        })
+            radius: PLAYER_RADIUS,
+        }
+    }
+
+    /// Recomputes the camera plane and projection distance after the render settings change.
+    pub fn set_projection(&mut self, settings: &RenderSettings) {
+        self.plane = settings.plane(self.dir_norm);
+        self.planedist = settings.planedist();
     }
 
-    pub fn draw_circle(&self, ctx: &mut Context) -> GameResult<()> {
-        graphics::draw(ctx, &self.mesh, DrawParam::default())?;
-        Ok(())
+    /// Whether the player stands on the floor, and so can jump.
+    pub fn on_ground(&self) -> bool {
+        self.z <= 0.0
     }
 
-    pub fn walk_animation(&mut self, buffer_walking: &[f32], time: f32) {
-        self.jump = self.height;
-        if self.walking {
-            let delta_jump = buffer_walking[(time % 0.5 * 300.0) as usize];
+    pub fn walk_animation(&mut self, time: f32) {
+        self.jump = (self.z + self.height - EYE_HEIGHT) * self.planedist;
+        if self.walking && self.on_ground() {
+            // A step every half second, in 150 stages.
+            let stage = (time % 0.5 * 300.0) as usize;
+            let delta_jump = (stage as f32 / 150.0 * 2.0 * std::f32::consts::PI).sin();
             self.jump += delta_jump * 35.0;
         }
     }
+
+    /// Pushes the player, e.g. when hit, by adding `impulse` in cells per second to their
+    /// velocity. The friction of the floor slows them down again.
+    pub fn knock_back(&mut self, impulse: Vector2<f32>) {
+        self.velocity += impulse;
+    }
+
+    /// Turns the player and tilts their view by the motion of the mouse, every frame.
+    pub fn look(&mut self, (delta_mouse_loc_x, delta_mouse_loc_y): (f32, f32)) {
+        let mut angle_of_rot = 0.0f32;
+        self.pitch -= delta_mouse_loc_y * 0.75;
+
+        self.pitch = clamp(self.pitch, -300.0, 300.0);
+
+        angle_of_rot += 0.085 * delta_mouse_loc_x;
+        self.plane = Vector2::rotate(self.plane, angle_of_rot.to_radians());
+        self.dir_norm = Vector2::rotate(self.dir_norm, angle_of_rot.to_radians());
+    }
+
+    /// Moves and acts on the map for one tick of input, bumping into its walls and solid
+    /// `sprites`. Returns the key missing to open the locked door the player tried to use.
+    pub fn apply_input(
+        &mut self,
+        input: &FrameInput,
+        map: &mut Map,
+        sprites: &[Sprite],
+    ) -> Option<Key> {
+        let dir_norm = self.dir_norm;
+        let mut dir = Vector2::new(0.0, 0.0);
+        self.walking = false;
+
+        if input.forward {
+            dir += dir_norm;
+            self.walking = true;
+        }
+        if input.backward {
+            dir -= dir_norm;
+            self.walking = true;
+        }
+
+        if input.left {
+            dir += Vector2::new(dir_norm.y, -dir_norm.x);
+            self.walking = true;
+        }
+        if input.right {
+            dir += Vector2::new(-dir_norm.y, dir_norm.x);
+            self.walking = true;
+        }
+
+        let (strafe, forward) = input.walk;
+        if strafe != 0.0 || forward != 0.0 {
+            dir += dir_norm * forward + Vector2::new(-dir_norm.y, dir_norm.x) * strafe;
+            self.walking = true;
+        }
+
+        // Keys walk at full speed, a stick as fast as it is tilted.
+        let speed = dir.magnitude().min(1.0);
+        dir.normalize();
+        self.velocity = map
+            .surface(self.pos)
+            .accelerate(self.velocity, dir * speed, input.sprint);
+        if self.velocity != Vector2::new(0.0, 0.0) {
+            let pos =
+                move_with_collision(map, sprites, self.pos, self.velocity * TICK, self.radius);
+            // Walls and sprites take away the speed that runs into them.
+            self.velocity = (pos - self.pos) * (1.0 / TICK);
+            self.pos = pos;
+        }
+
+        let mut locked = None;
+        if input.open && !self.using {
+            if let Some(cell) = self.cell_in_reach(map) {
+                match map.doors.get_mut(&cell) {
+                    Some(door) => match door.lock {
+                        Some(key) if !self.keys.contains(&key) => locked = Some(key),
+                        _ => door.toggle(),
+                    },
+                    None => {
+                        map.push_wall(cell, self.pos, sprites);
+                    }
+                }
+            }
+        }
+        self.using = input.open;
+
+        self.fall(input, map.ceiling_height(self.pos));
+        locked
+    }
+
+    /// First door or wall along the view within [`USE_REACH`], other than the cell the player
+    /// stands in.
+    fn cell_in_reach(&self, map: &Map) -> Option<usize> {
+        let (width, height) = (map.size.0 as f32, map.size.1 as f32);
+        let here = self.pos.x as usize + self.pos.y as usize * map.size.0;
+        (1..=(USE_REACH / USE_STEP) as usize)
+            .map(|step| self.pos + self.dir_norm * (step as f32 * USE_STEP))
+            .take_while(|p| p.x >= 0.0 && p.y >= 0.0 && p.x < width && p.y < height)
+            .map(|p| p.x as usize + p.y as usize * map.size.0)
+            .find(|&i| i != here && (map.solid[i] || map.doors.contains_key(&i)))
+    }
+
+    /// Takes the keys lying within reach out of `sprites`, and returns them.
+    pub fn pick_up_keys(&mut self, sprites: &mut Vec<Sprite>) -> Vec<Key> {
+        let (pos, reach) = (self.pos, self.radius + KEY_REACH);
+        let mut found = Vec::new();
+        sprites.retain(|sprite| match sprite.key() {
+            Some(key) if (sprite.pos - pos).magnitude() < reach => {
+                found.push(key);
+                false
+            }
+            _ => true,
+        });
+        self.keys.extend(&found);
+        found
+    }
+
+    /// Jumps, falls back to the floor and crouches for one tick, under a ceiling `ceiling`
+    /// cells above the floor.
+    fn fall(&mut self, input: &FrameInput, ceiling: f32) {
+        if input.jump && self.on_ground() {
+            self.vertical_velocity = JUMP_SPEED;
+        }
+        self.vertical_velocity -= GRAVITY * TICK;
+        self.z += self.vertical_velocity * TICK;
+        if self.z <= 0.0 {
+            self.z = 0.0;
+            self.vertical_velocity = 0.0;
+        }
+        // Bumping the head on the ceiling ends the jump.
+        let highest = (ceiling - self.height - HEAD_ROOM).max(0.0);
+        if self.z > highest {
+            self.z = highest;
+            self.vertical_velocity = self.vertical_velocity.min(0.0);
+        }
+
+        let step = CROUCH_SPEED * TICK;
+        if input.crouch {
+            self.height = (self.height - step).max(CROUCH_EYE_HEIGHT);
+        } else {
+            // Standing up needs room for the head.
+            let room = ceiling - self.z - HEAD_ROOM;
+            self.height = (self.height + step)
+                .min(EYE_HEIGHT)
+                .min(room.max(self.height));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::DoorState;
+    use crate::sprite::SpriteType;
+
+    fn player() -> Player {
+        let dir = Vector2::new(1.0, 0.0);
+        let settings = RenderSettings::default();
+        Player::new(
+            Vector2::new(1.5, 1.5),
+            dir,
+            settings.plane(dir),
+            settings.planedist(),
+            0.0,
+            0.0,
+        )
+    }
+
+    fn ticks(player: &mut Player, input: &FrameInput, ceiling: f32, seconds: f32) {
+        for _ in 0..(seconds / TICK).round() as usize {
+            player.fall(input, ceiling);
+        }
+    }
+
+    #[test]
+    fn jumps_and_lands_back_on_the_floor() {
+        let mut player = player();
+        let jump = FrameInput {
+            jump: true,
+            ..FrameInput::idle(TICK)
+        };
+        player.fall(&jump, 1.0);
+
+        let (mut highest, mut airborne) = (player.z, TICK);
+        while !player.on_ground() {
+            // Holding jump in the air doesn't jump again.
+            player.fall(&jump, 1.0);
+            highest = highest.max(player.z);
+            airborne += TICK;
+        }
+        assert!((0.25..0.3).contains(&highest), "{}", highest);
+        assert!((0.45..0.55).contains(&airborne), "{}", airborne);
+        assert_eq!(player.vertical_velocity, 0.0);
+    }
+
+    #[test]
+    fn stays_crouched_under_low_ceilings() {
+        let mut player = player();
+        let crouch = FrameInput {
+            crouch: true,
+            ..FrameInput::idle(TICK)
+        };
+        ticks(&mut player, &crouch, 1.0, 0.5);
+        assert_eq!(player.height, CROUCH_EYE_HEIGHT);
+        player.walk_animation(0.0);
+        assert!(player.jump < 0.0);
+
+        ticks(&mut player, &FrameInput::idle(TICK), 0.5, 0.5);
+        assert!((player.height - (0.5 - HEAD_ROOM)).abs() < 1e-6);
+        ticks(&mut player, &FrameInput::idle(TICK), 1.0, 0.5);
+        assert_eq!(player.height, EYE_HEIGHT);
+        player.walk_animation(0.0);
+        assert_eq!(player.jump, 0.0);
+    }
+
+    #[test]
+    fn locked_doors_open_once_the_key_is_picked_up() {
+        let (mut map, spawn) = Map::from_ascii(
+            "
+            #####
+            #P.D#
+            #####
+            ",
+        )
+        .unwrap();
+        map.doors.get_mut(&8).unwrap().lock = Some(Key::Red);
+        let mut sprites = vec![
+            Sprite::new(SpriteType::Armor, Vector2::new(3.5, 0.5)),
+            Sprite::new(SpriteType::RedKey, Vector2::new(1.6, 1.5)),
+        ];
+        let mut player = player();
+        player.pos = spawn.unwrap();
+        let open = FrameInput {
+            open: true,
+            ..FrameInput::idle(TICK)
+        };
+        let idle = FrameInput::idle(TICK);
+
+        assert_eq!(player.apply_input(&open, &mut map, &[]), Some(Key::Red));
+        assert_eq!(map.doors[&8].state, DoorState::Closed);
+
+        assert_eq!(player.pick_up_keys(&mut sprites), vec![Key::Red]);
+        assert_eq!(sprites.len(), 1);
+        player.apply_input(&idle, &mut map, &[]);
+        assert_eq!(player.apply_input(&open, &mut map, &[]), None);
+        assert_eq!(map.doors[&8].state, DoorState::Opening);
+    }
+
+    #[test]
+    fn pushes_the_wall_it_stands_against() {
+        let (mut map, _) = Map::from_ascii(
+            "
+            #######
+            #..S..#
+            #######
+            ",
+        )
+        .unwrap();
+        let mut player = player();
+        player.pos = Vector2::new(3.0 - player.radius, 1.5);
+        let open = FrameInput {
+            open: true,
+            ..FrameInput::idle(TICK)
+        };
+
+        assert_eq!(player.apply_input(&open, &mut map, &[]), None);
+        assert_eq!(map.push_walls[&(3 + 7)].dir, Some((1, 0)));
+    }
 }
@@ -0,0 +1,998 @@
+use std::path::Path;
+
+use ggez::GameResult;
+use rayon::prelude::*;
+
+use crate::lighting::{Lighting, Torch};
+use crate::map::{DoorKind, Map};
+use crate::player::Player;
+use crate::screen::Screen;
+use crate::sprite::Sprite;
+use crate::utilities::vector2::Vector2;
+use crate::Orientation;
+
+/// Resolution and projection used by the `Renderer`, which can be changed while the game runs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderSettings {
+    pub width: usize,
+    pub height: usize,
+    /// Horizontal field of view, in degrees.
+    pub fov: f32,
+    /// Width in pixels of the column drawn by each ray.
+    pub rays_per_pixel: usize,
+}
+
+impl RenderSettings {
+    pub fn new(width: usize, height: usize, fov: f32, rays_per_pixel: usize) -> Self {
+        Self {
+            width,
+            height,
+            fov,
+            rays_per_pixel,
+        }
+    }
+
+    /// Distance from the player to the projection plane.
+    pub fn planedist(&self) -> f32 {
+        (self.width as f32 * 0.5) / (self.fov.to_radians() * 0.5).tan()
+    }
+
+    /// Camera plane vector for a player looking towards `dir_norm`.
+    pub fn plane(&self, dir_norm: Vector2<f32>) -> Vector2<f32> {
+        Vector2::new(-dir_norm.y, dir_norm.x) * (self.fov.to_radians() * 0.5).tan()
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self::new(1920, 1080, 45.0, 2)
+    }
+}
+
+/// Software renderer that produces a whole frame on the CPU, without needing a `ggez::Context`.
+///
+/// The frame is stored column by column in `screen.img_arr` (each ray writes a contiguous
+/// slice), so it has to be rotated when presented. Use `frame` to get it in row-major order.
+pub struct Renderer {
+    settings: RenderSettings,
+    cell_size: f32,
+    angles: Vec<f32>,
+    buffer_floors: Vec<f32>,
+    intersections: Intersections,
+    pub screen: Screen,
+}
+
+/// Everything `draw_slice` reads while the columns are drawn in parallel.
+struct Frame<'a> {
+    map: &'a Map,
+    player: &'a Player,
+    sprites: &'a [Sprite],
+    lighting: &'a Lighting,
+    intensity: f32,
+}
+
+impl Renderer {
+    pub fn new(settings: RenderSettings, wall_textures: Vec<u8>, sprite_textures: Vec<u8>) -> Self {
+        let mut screen = Screen::new(0.0, 0.0, 128, 128 * 8);
+        screen.textures(wall_textures, sprite_textures);
+
+        let mut renderer = Self {
+            settings,
+            cell_size: 128.0,
+            angles: Vec::new(),
+            buffer_floors: Vec::new(),
+            intersections: Intersections::new(0, 1),
+            screen,
+        };
+        renderer.set_settings(settings);
+        renderer
+    }
+
+    pub fn settings(&self) -> RenderSettings {
+        self.settings
+    }
+
+    /// Applies new settings, reallocating every buffer that depends on the resolution.
+    ///
+    /// The width is rounded down to a multiple of `rays_per_pixel` so every ray gets a full column,
+    /// but never below the column of a single ray.
+    pub fn set_settings(&mut self, mut settings: RenderSettings) {
+        settings.rays_per_pixel = settings.rays_per_pixel.max(1);
+        settings.width = (settings.width - settings.width % settings.rays_per_pixel)
+            .max(settings.rays_per_pixel);
+        let (width, height) = (settings.width as f32, settings.height as f32);
+        let rays = settings.width / settings.rays_per_pixel;
+        let planedist = settings.planedist();
+        // Rays spread from edge to edge of the screen, a single ray looks straight ahead.
+        let spacing = width / (rays as f32 - 1.0).max(1.0);
+
+        self.angles = (0..rays)
+            .map(|r: usize| ((r as f32 - (rays as f32 - 1.0) * 0.5) * spacing / planedist).atan())
+            .collect();
+
+        self.buffer_floors = (0..settings.height)
+            .map(|y| planedist / (2.0 * y as f32 - height))
+            .collect();
+
+        self.intersections = Intersections::new(settings.width, settings.rays_per_pixel);
+        self.screen.resize(height, width);
+        self.settings = settings;
+    }
+
+    /// Casts every ray from the player's point of view and draws walls, floor, ceiling and sprites.
+    pub fn render(
+        &mut self,
+        map: &Map,
+        player: &Player,
+        sprites: &mut [Sprite],
+        lighting: &Lighting,
+        torch: &Torch,
+    ) {
+        let (w, h) = (self.settings.width as f32, self.settings.height as f32);
+        let rays_per_pixel = self.settings.rays_per_pixel;
+
+        for j in 0..self.angles.len() {
+            self.calculate_ray(map, player, self.angles[j], j);
+        }
+
+        (0..h as usize).for_each(|y| {
+            if y < (player.pitch + h * 0.5) as usize {
+                // Calculate ceiling y buffer
+                self.buffer_floors[y] =
+                    (player.planedist - 2.0 * player.jump) / (-2.0 * (y as f32 - player.pitch) + h);
+            }
+            if y > (h * 0.5 + player.pitch) as usize {
+                // Calculate floor y buffer
+                self.buffer_floors[y] =
+                    (player.planedist + 2.0 * player.jump) / (2.0 * (-player.pitch + y as f32) - h);
+            }
+        });
+
+        sprites
+            .iter_mut()
+            .for_each(|sprite| sprite.set_drawing_bounds(player, (w, h), rays_per_pixel as f32));
+
+        sprites.sort_by(|a: &Sprite, b: &Sprite| {
+            b.calculate_distance_2(player)
+                .partial_cmp(&a.distance2)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let frame = Frame {
+            map,
+            player,
+            sprites,
+            lighting,
+            intensity: torch.intensity,
+        };
+        let mut img_arr = std::mem::take(&mut self.screen.img_arr);
+
+        img_arr
+            .par_chunks_mut(h as usize * 4 * rays_per_pixel)
+            .enumerate()
+            .for_each(|(j, slice)| {
+                self.draw_slice(slice, w as usize / rays_per_pixel - j - 1, &frame)
+            });
+
+        self.screen.img_arr = img_arr;
+    }
+
+    /// Returns the last rendered frame as row-major RGBA, `width * height * 4` bytes long.
+    pub fn frame(&self) -> Vec<u8> {
+        let (w, h) = (self.settings.width, self.settings.height);
+        let mut rgba = vec![0; w * h * 4];
+        rgba.chunks_mut(4).enumerate().for_each(|(i, pixel)| {
+            let (x, y) = (i % w, i / w);
+            let pos = ((w - x - 1) * h + y) << 2;
+            pixel.copy_from_slice(&self.screen.img_arr[pos..pos + 4]);
+        });
+        rgba
+    }
+
+    fn calculate_ray(&mut self, map: &Map, player: &Player, theta: f32, j: usize) {
+        let ray_dir_norm = Vector2::rotate(player.dir_norm, theta);
+        let ray_unitstep_size = Vector2::new(
+            (1.0 + (ray_dir_norm.y / ray_dir_norm.x) * (ray_dir_norm.y / ray_dir_norm.x)).sqrt(),
+            (1.0 + (ray_dir_norm.x / ray_dir_norm.y) * (ray_dir_norm.x / ray_dir_norm.y)).sqrt(),
+        );
+        let startv = player.pos;
+        let map_size = map.size;
+
+        let mut map_checkv = Vector2::new(startv.x.floor(), startv.y.floor());
+        let mut ray_length1_d = Vector2::new(0.0f32, 0.0);
+        let mut orientation;
+        let mut stepv = Vector2::new(0.0f32, 0.0);
+        let mut last_was_door = false;
+
+        if ray_dir_norm.x < 0.0 {
+            stepv.x = -1.0;
+            ray_length1_d.x = (startv.x - map_checkv.x) * ray_unitstep_size.x;
+        } else {
+            stepv.x = 1.0;
+            ray_length1_d.x = (map_checkv.x + 1.0 - startv.x) * ray_unitstep_size.x;
+        }
+
+        if ray_dir_norm.y < 0.0 {
+            stepv.y = -1.0;
+            ray_length1_d.y = (startv.y - map_checkv.y) * ray_unitstep_size.y;
+        } else {
+            stepv.y = 1.0;
+            ray_length1_d.y = (map_checkv.y + 1.0 - startv.y) * ray_unitstep_size.y;
+        }
+
+        let mut tilefound = false;
+        let mut distance = 0.0;
+        self.intersections.lifts[j] = None;
+
+        while !tilefound && distance < 100.0 {
+            //arbitrary max distance
+
+            if ray_length1_d.x < ray_length1_d.y {
+                map_checkv.x += stepv.x;
+                distance = ray_length1_d.x;
+                ray_length1_d.x += ray_unitstep_size.x;
+
+                if ray_dir_norm.x < 0.0 {
+                    orientation = Orientation::W;
+                } else {
+                    orientation = Orientation::E;
+                }
+            } else {
+                map_checkv.y += stepv.y;
+                distance = ray_length1_d.y;
+                ray_length1_d.y += ray_unitstep_size.y;
+
+                if ray_dir_norm.y < 0.0 {
+                    orientation = Orientation::S;
+                } else {
+                    orientation = Orientation::N;
+                }
+            }
+            if map_checkv.x >= 0.0
+                && map_checkv.x < map_size.0 as f32
+                && map_checkv.y >= 0.0
+                && map_checkv.y < map_size.1 as f32
+            {
+                let cell = map_checkv.y as usize * map_size.0 + map_checkv.x as usize;
+                let mut wall_type = map.walls[cell];
+                let mut offset = [0.0, 0.0];
+
+                if last_was_door && wall_type > 0 {
+                    wall_type = 7;
+                }
+                last_was_door = false;
+                if wall_type == 6 {
+                    //door
+                    let door = map.doors.get(&cell).expect("error finding door");
+                    let door_offset = door.offset;
+
+                    tilefound = true;
+                    if orientation == Orientation::N || orientation == Orientation::S {
+                        if ray_length1_d.y - 0.5 * ray_unitstep_size.y < ray_length1_d.x {
+                            distance = ray_length1_d.y - ray_unitstep_size.y * 0.5;
+
+                            if door_offset < 1.0 {
+                                let pos_x = (startv.x + ray_dir_norm.x * distance) % 1.0;
+                                if door.kind == DoorKind::Lift
+                                    && door_offset > 0.0
+                                    && self.intersections.lifts[j].is_none()
+                                {
+                                    self.intersections.lifts[j] = Some(LiftHit {
+                                        cell,
+                                        orientation,
+                                        offset: door_offset,
+                                        along: pos_x,
+                                        distance: distance * theta.cos(),
+                                        distance_fisheye: distance,
+                                    });
+                                }
+                                if door.kind.gap(pos_x, door_offset) {
+                                    last_was_door = true;
+                                    tilefound = false;
+                                }
+                            }
+                        } else {
+                            // side wall
+                            if ray_dir_norm.x < 0.0 {
+                                orientation = Orientation::W;
+                                map_checkv.x -= 1.0;
+                            } else {
+                                orientation = Orientation::E;
+                                map_checkv.x += 1.0;
+                            }
+                            wall_type = 7;
+                            distance = ray_length1_d.x;
+                        }
+                    } else if orientation == Orientation::E || orientation == Orientation::W {
+                        if ray_length1_d.x - 0.5 * ray_unitstep_size.x <= ray_length1_d.y {
+                            distance = ray_length1_d.x - ray_unitstep_size.x * 0.5;
+                            if door_offset < 1.0 {
+                                let pos_y = (startv.y + ray_dir_norm.y * distance) % 1.0;
+                                if door.kind == DoorKind::Lift
+                                    && door_offset > 0.0
+                                    && self.intersections.lifts[j].is_none()
+                                {
+                                    self.intersections.lifts[j] = Some(LiftHit {
+                                        cell,
+                                        orientation,
+                                        offset: door_offset,
+                                        along: pos_y,
+                                        distance: distance * theta.cos(),
+                                        distance_fisheye: distance,
+                                    });
+                                }
+                                if door.kind.gap(pos_y, door_offset) {
+                                    last_was_door = true;
+                                    tilefound = false;
+                                }
+                            }
+                        } else {
+                            if ray_dir_norm.y < 0.0 {
+                                orientation = Orientation::S;
+                                map_checkv.y -= 1.0;
+                            } else {
+                                orientation = Orientation::N;
+                                map_checkv.y += 1.0;
+                            }
+                            wall_type = 7;
+                            distance = ray_length1_d.y;
+                        }
+                    }
+                } else if wall_type > 0 {
+                    tilefound = true;
+                }
+                if ((orientation == Orientation::W || orientation == Orientation::E)
+                    && map.walls
+                        [startv.y as usize * map_size.0 + (map_checkv.x - stepv.x) as usize]
+                        == 6)
+                    || ((orientation == Orientation::N || orientation == Orientation::S)
+                        && map.walls
+                            [(map_checkv.y - stepv.y) as usize * map_size.0 + startv.x as usize]
+                            == 6)
+                {
+                    wall_type = 7;
+                }
+                if let Some(wall) = map.moving_push_wall(cell).filter(|_| !tilefound) {
+                    // The sliding wall is a cell wide box partly in the next cell, hit here only
+                    // if the ray meets it before leaving this cell.
+                    let corner = wall.corner(map_size.0);
+                    if let Some((hit, side_x)) = enter_box(startv, ray_dir_norm, corner)
+                        .filter(|&(hit, _)| hit <= ray_length1_d.x.min(ray_length1_d.y))
+                    {
+                        tilefound = true;
+                        distance = hit;
+                        wall_type = wall.texture;
+                        offset = [corner.x.fract(), corner.y.fract()];
+                        orientation = match (side_x, ray_dir_norm.x < 0.0, ray_dir_norm.y < 0.0) {
+                            (true, true, _) => Orientation::W,
+                            (true, false, _) => Orientation::E,
+                            (false, _, true) => Orientation::S,
+                            (false, _, false) => Orientation::N,
+                        };
+                    }
+                }
+                if tilefound {
+                    self.intersections.offsets[j] = offset;
+                    let intersection = startv + ray_dir_norm * distance;
+                    self.intersections.points[j] = intersection.to_array();
+                    self.intersections.distance_fisheye[j] = distance;
+                    distance *= (theta).cos();
+
+                    self.intersections.distances[j] = distance;
+                    self.intersections.wall_type[j] = wall_type;
+                    self.intersections.map_checkv[j] =
+                        map_checkv.y as usize * map_size.0 + map_checkv.x as usize;
+                    self.intersections.orientation[j] = orientation;
+                }
+            }
+        }
+    }
+
+    fn draw_slice(&self, slice: &mut [u8], j: usize, frame: &Frame) {
+        let Frame {
+            map,
+            player,
+            sprites,
+            lighting,
+            intensity,
+        } = *frame;
+        let h = self.settings.height as f32;
+        let rays_per_pixel = self.settings.rays_per_pixel;
+        let rect_h = (player.planedist / (self.intersections.distances[j]) * 100.0).round() / 100.0;
+        let rect_top = (h - rect_h) * 0.5;
+        let rect_bottom = (h + rect_h) * 0.5;
+        let ty_step = (self.cell_size) / rect_h;
+        let pos = self.intersections.points[j];
+
+        // Texture coordinates count from the corner of the wall, which a push-wall moves.
+        let offset = self.intersections.offsets[j];
+        let inter_x = (pos[0] - offset[0]) - (pos[0] - offset[0]).floor();
+        let inter_y = (pos[1] - offset[1]) - (pos[1] - offset[1]).floor();
+
+        let wall_type = self.intersections.wall_type[j];
+
+        let pos_z = player.jump / (self.intersections.distances[j]);
+        //draw walls
+        let mut ty = {
+            if rect_bottom - player.pitch - pos_z >= h {
+                (-player.pitch - pos_z - rect_top) * ty_step
+            } else if rect_top + player.pitch + pos_z < 0.0 {
+                (-player.pitch - pos_z - rect_bottom) * ty_step
+            } else {
+                0.0
+            }
+        };
+
+        let mut tx;
+        match self.intersections.orientation[j] {
+            Orientation::N => {
+                tx = inter_x * self.cell_size;
+                tx = self.cell_size - 1.0 - tx.floor();
+            }
+            Orientation::E => {
+                tx = inter_y * self.cell_size;
+            }
+            Orientation::S => {
+                tx = inter_x * self.cell_size;
+            }
+            Orientation::W => {
+                tx = inter_y * self.cell_size;
+                tx = self.cell_size - 1.0 - tx.floor();
+            }
+        }
+        let door = (wall_type == 6).then(|| {
+            map.doors
+                .get(&self.intersections.map_checkv[j])
+                .expect("error to draw door")
+        });
+        let texture = door.map_or(wall_type, |door| door.kind.texture());
+        match door.map(|door| (door.kind, 1.0 - door.offset)) {
+            Some((DoorKind::Slide, offset)) => {
+                // The panel slides into the wall at the start of the doorway.
+                let along = match self.intersections.orientation[j] {
+                    Orientation::N | Orientation::S => inter_x,
+                    Orientation::E | Orientation::W => inter_y,
+                };
+                tx = ((along + offset) * self.cell_size).min(self.cell_size - 1.0);
+            }
+            Some((DoorKind::Split, offset)) => match self.intersections.orientation[j] {
+                Orientation::N => {
+                    if inter_x < 0.5 {
+                        tx -= offset * 64.0;
+                    } else {
+                        tx += offset * 64.0;
+                    }
+                }
+                Orientation::E => {
+                    if inter_y > 0.5 {
+                        tx -= offset * 64.0;
+                    } else {
+                        tx += offset * 64.0;
+                    }
+                }
+                Orientation::S => {
+                    if inter_x > 0.5 {
+                        tx -= offset * 64.0;
+                    } else {
+                        tx += offset * 64.0;
+                    }
+                }
+                Orientation::W => {
+                    if inter_y < 0.5 {
+                        tx -= offset * 64.0;
+                    } else {
+                        tx += offset * 64.0;
+                    }
+                }
+            },
+            _ => (),
+        }
+
+        let rect_bottom_draw = {
+            if player.pitch + pos_z + rect_bottom >= h {
+                h - player.pitch - pos_z
+            } else {
+                rect_bottom
+            }
+        };
+
+        for y in (player.pitch + pos_z + rect_top) as usize
+            ..(player.pitch + pos_z + rect_bottom_draw) as usize
+        {
+            if ty >= 128.0 {
+                ty = 127.0;
+            }
+
+            self.screen.draw_texture(
+                slice,
+                [tx as usize, texture * 128 + ty as usize],
+                y,
+                rays_per_pixel,
+                intensity
+                    * lighting.get_lighting_wall(
+                        tx / 128.0,
+                        ty * 0.0234375, //*3.0/128.0
+                        self.intersections.map_checkv[j],
+                        &self.intersections.orientation[j],
+                    ),
+                (3.0 / (self.intersections.distance_fisheye[j]
+                    * self.intersections.distance_fisheye[j]))
+                    .min(1.5),
+            );
+            ty += ty_step;
+        }
+
+        //draw floor
+        for y in (player.pitch + pos_z + rect_bottom) as usize..(h) as usize {
+            let current_dist = self.buffer_floors[y]; // Use a buffer since they're always the same values
+            let weight = current_dist / (self.intersections.distances[j]);
+
+            let rhs = player.pos * (1.0 - weight);
+            let current_floor_x = weight * pos[0] + rhs.x;
+            let current_floor_y = weight * pos[1] + rhs.y;
+
+            let location = current_floor_x as usize + current_floor_y as usize * map.size.0;
+            let floor_type = map.floors[location];
+
+            let ftx = (current_floor_x * self.cell_size) as usize % 128;
+            let fty = (current_floor_y * self.cell_size) as usize % 128;
+            let lighting =
+                lighting.get_lighting_floor(ftx as f32 / 128.0, fty as f32 / 128.0, location);
+            self.screen.draw_texture(
+                slice,
+                [ftx, (floor_type * 128) + fty],
+                y,
+                rays_per_pixel,
+                intensity * lighting,
+                (3.0 / (current_dist * current_dist)).min(1.5),
+            )
+        }
+        //draw ceiling
+        let mut rect_top_draw = rect_top;
+        if rect_top + player.pitch + pos_z > h {
+            rect_top_draw = h - player.pitch - pos_z;
+        }
+        for y in 0..(rect_top_draw + player.pitch + pos_z) as usize {
+            let current_dist = self.buffer_floors[y];
+            let weight = current_dist / (self.intersections.distances[j]);
+
+            let rhs = player.pos * (1.0 - weight);
+            let current_floor_x = weight * pos[0] + rhs.x;
+            let current_floor_y = weight * pos[1] + rhs.y;
+
+            let location = current_floor_x as usize + current_floor_y as usize * map.size.0;
+            let ceiling_type = map.ceilings[location];
+
+            let ftx = (current_floor_x * self.cell_size) as usize % 128;
+            let fty = (current_floor_y * self.cell_size) as usize % 128;
+
+            self.screen.draw_texture(
+                slice,
+                [ftx, (ceiling_type * 128) + fty],
+                y,
+                rays_per_pixel,
+                intensity
+                    * lighting.get_lighting_floor(ftx as f32 / 128.0, fty as f32 / 128.0, location),
+                (3.0 / (current_dist * current_dist)).min(1.5),
+            );
+        }
+
+        // Sprites are sorted from far to near, so the ones behind a rising door come first.
+        let lift = self.intersections.lifts[j];
+        let behind = |sprite: &&Sprite| {
+            lift.is_some_and(|lift| {
+                sprite.distance2 > lift.distance_fisheye * lift.distance_fisheye
+            })
+        };
+        let draw_sprite = |slice: &mut [u8], sprite: &Sprite| {
+            sprite.draw(
+                slice,
+                player,
+                j,
+                &self.screen,
+                self.intersections.distances[j],
+                rays_per_pixel,
+            )
+        };
+        for sprite in sprites.iter().filter(behind) {
+            draw_sprite(slice, sprite);
+        }
+        if let Some(lift) = lift {
+            self.draw_lift(slice, &lift, frame);
+        }
+        for sprite in sprites.iter().filter(|sprite| !behind(sprite)) {
+            draw_sprite(slice, sprite);
+        }
+    }
+
+    /// Draws the part of a rising door still hanging into its doorway, over what the ray hit
+    /// behind it. The door is cut off at its bottom edge, which rises as the door opens.
+    fn draw_lift(&self, slice: &mut [u8], lift: &LiftHit, frame: &Frame) {
+        let player = frame.player;
+        let h = self.settings.height as f32;
+        let rect_h = player.planedist / lift.distance;
+        let top = (h - rect_h) * 0.5 + player.pitch + player.jump / lift.distance;
+        let bottom = top + rect_h * lift.offset;
+        let ty_step = self.cell_size / rect_h;
+        let tx = (lift.along * self.cell_size).min(self.cell_size - 1.0);
+        let texture = DoorKind::Lift.texture();
+
+        let first = top.clamp(0.0, h);
+        // The door has risen by the share of it that no longer covers the doorway.
+        let mut ty = (1.0 - lift.offset) * self.cell_size + (first - top) * ty_step;
+        for y in first as usize..bottom.clamp(0.0, h) as usize {
+            let ty_draw = ty.min(self.cell_size - 1.0);
+            self.screen.draw_texture(
+                slice,
+                [tx as usize, texture * 128 + ty_draw as usize],
+                y,
+                self.settings.rays_per_pixel,
+                frame.intensity
+                    * frame.lighting.get_lighting_wall(
+                        tx / 128.0,
+                        ty_draw * 0.0234375, //*3.0/128.0
+                        lift.cell,
+                        &lift.orientation,
+                    ),
+                (3.0 / (lift.distance_fisheye * lift.distance_fisheye)).min(1.5),
+            );
+            ty += ty_step;
+        }
+    }
+}
+
+/// Distance along the ray from `start` towards `dir` to where it enters the box a cell wide
+/// whose top left corner is `corner`, and whether it enters through a side facing along x.
+fn enter_box(start: Vector2<f32>, dir: Vector2<f32>, corner: Vector2<f32>) -> Option<(f32, bool)> {
+    let slab = |start: f32, dir: f32, low: f32| {
+        let (a, b) = ((low - start) / dir, (low + 1.0 - start) / dir);
+        (a.min(b), a.max(b))
+    };
+    let (near_x, far_x) = slab(start.x, dir.x, corner.x);
+    let (near_y, far_y) = slab(start.y, dir.y, corner.y);
+    let (near, far) = (near_x.max(near_y), far_x.min(far_y));
+    (near >= 0.0 && near <= far).then_some((near, near_x > near_y))
+}
+
+/// Decodes an image file from disk into RGBA pixels.
+pub fn read_rgba8(path: &Path) -> GameResult<Vec<u8>> {
+    Ok(image::open(path)?.to_rgba8().into_raw())
+}
+
+/// Writes a row-major RGBA frame to disk, as PNG or PPM depending on the file extension.
+pub fn write_frame(path: &Path, rgba: Vec<u8>, width: usize, height: usize) -> GameResult {
+    let img = image::RgbaImage::from_raw(width as u32, height as u32, rgba)
+        .expect("frame size doesn't match its dimensions");
+    if path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("ppm"))
+    {
+        // PPM can't store an alpha channel
+        image::DynamicImage::ImageRgba8(img).to_rgb8().save(path)?;
+    } else {
+        img.save(path)?;
+    }
+    Ok(())
+}
+
+pub struct Intersections {
+    points: Vec<[f32; 2]>,
+    distances: Vec<f32>,
+    distance_fisheye: Vec<f32>,
+    map_checkv: Vec<usize>,
+    orientation: Vec<Orientation>,
+    wall_type: Vec<usize>,
+    /// How far the wall each ray hit is moved from the corner of its cell, along x and y.
+    offsets: Vec<[f32; 2]>,
+    /// Rising door each ray passed under on its way to the wall, drawn in front of it.
+    lifts: Vec<Option<LiftHit>>,
+}
+
+/// Where a ray passed under a door that is rising out of its doorway.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct LiftHit {
+    cell: usize,
+    orientation: Orientation,
+    /// Share of the doorway the door still covers.
+    offset: f32,
+    /// Where the ray crossed the doorway, from 0 to 1 across it.
+    along: f32,
+    distance: f32,
+    distance_fisheye: f32,
+}
+
+impl Intersections {
+    pub fn new(w: usize, rays_per_pixel: usize) -> Self {
+        let rays = w / rays_per_pixel;
+        Self {
+            points: vec![[0.0, 0.0]; rays],
+            distances: vec![0.0; rays],
+            distance_fisheye: vec![0.0; rays],
+            map_checkv: vec![0; rays],
+            orientation: vec![Orientation::N; rays],
+            wall_type: vec![0; rays],
+            offsets: vec![[0.0, 0.0]; rays],
+            lifts: vec![None; rays],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::palette::Palette;
+
+    const WIDTH: usize = 320;
+    const HEIGHT: usize = 180;
+
+    fn settings() -> RenderSettings {
+        RenderSettings::new(WIDTH, HEIGHT, 45.0, 2)
+    }
+
+    fn player(pos: Vector2<f32>) -> Player {
+        let dir_norm = Vector2::new(0.0, -1.0);
+        Player::new(
+            pos,
+            dir_norm,
+            settings().plane(dir_norm),
+            settings().planedist(),
+            0.0,
+            0.0,
+        )
+    }
+
+    fn rgba8(path: &str) -> Vec<u8> {
+        image::open(path).unwrap().to_rgba8().into_raw()
+    }
+
+    #[test]
+    fn renders_level_without_context() {
+        let map = Map::load_file(
+            Path::new("assets/map.png"),
+            Path::new("assets/floor.png"),
+            &Palette::default(),
+        )
+        .unwrap();
+        let lighting = Lighting::new(vec![1 + 16 * 3], &map.solid, map.size);
+        let mut renderer = Renderer::new(
+            settings(),
+            rgba8("assets/wall128.png"),
+            rgba8("assets/sprite128.png"),
+        );
+
+        renderer.render(
+            &map,
+            &player(Vector2::new(8.5, 12.5)),
+            &mut [],
+            &lighting,
+            &Torch::with_intensity(1.0),
+        );
+
+        let frame = renderer.frame();
+        assert_eq!(frame.len(), WIDTH * HEIGHT * 4);
+        assert!(frame.chunks(4).all(|pixel| pixel[3] == 255));
+    }
+
+    #[test]
+    fn wall_fills_frame_up_close() {
+        // 3x3 room of black walls around a single empty cell.
+        let mut walls = [0, 0, 0, 255].repeat(9);
+        walls[16..20].copy_from_slice(&[255, 255, 255, 255]);
+        let floors = vec![255; 9 * 4];
+        let map = Map::from_rgba8(&walls, &floors, (3, 3), &Palette::default()).unwrap();
+        let lighting = Lighting::new(vec![4], &map.solid, map.size);
+
+        // Wall texture 2 is red, every other texture is green.
+        let mut wall_textures = [0, 255, 0, 255].repeat(128 * 128 * 9);
+        wall_textures[128 * 128 * 2 * 4..128 * 128 * 3 * 4]
+            .copy_from_slice(&[255, 0, 0, 255].repeat(128 * 128));
+        let player = player(Vector2::new(1.5, 1.5));
+        let mut renderer = Renderer::new(settings(), wall_textures, vec![]);
+
+        renderer.render(
+            &map,
+            &player,
+            &mut [],
+            &lighting,
+            &Torch::with_intensity(1.0),
+        );
+
+        // Half a cell away from every wall, so no floor or ceiling is visible.
+        let frame = renderer.frame();
+        assert!(frame
+            .chunks(4)
+            .all(|pixel| pixel[0] > 0 && pixel[1] == 0 && pixel[3] == 255));
+    }
+
+    #[test]
+    fn settings_change_reallocates_buffers() {
+        let map = Map::load_file(
+            Path::new("assets/map.png"),
+            Path::new("assets/floor.png"),
+            &Palette::default(),
+        )
+        .unwrap();
+        let lighting = Lighting::new(vec![1 + 16 * 3], &map.solid, map.size);
+        let mut renderer = Renderer::new(
+            settings(),
+            rgba8("assets/wall128.png"),
+            rgba8("assets/sprite128.png"),
+        );
+
+        // Odd widths are rounded down so that every ray draws a full column.
+        let wide = RenderSettings::new(641, 120, 90.0, 2);
+        renderer.set_settings(wide);
+        let mut player = player(Vector2::new(8.5, 12.5));
+        player.set_projection(&renderer.settings());
+        renderer.render(
+            &map,
+            &player,
+            &mut [],
+            &lighting,
+            &Torch::with_intensity(1.0),
+        );
+
+        assert_eq!(renderer.settings().width, 640);
+        assert_eq!(renderer.angles.len(), 320);
+        assert_eq!(player.planedist, renderer.settings().planedist());
+        let frame = renderer.frame();
+        assert_eq!(frame.len(), 640 * 120 * 4);
+        assert!(frame.chunks(4).all(|pixel| pixel[3] == 255));
+    }
+
+    #[test]
+    fn single_ray_looks_straight_ahead() {
+        let mut renderer = Renderer::new(settings(), vec![], vec![]);
+        renderer.set_settings(RenderSettings::new(1, 1, 45.0, 1));
+
+        assert_eq!(renderer.angles, vec![0.0]);
+    }
+
+    #[test]
+    fn windows_narrower_than_a_ray_keep_one_ray() {
+        let mut renderer = Renderer::new(settings(), vec![], vec![]);
+        renderer.set_settings(RenderSettings::new(3, 10, 45.0, 4));
+
+        assert_eq!(renderer.settings().width, 4);
+        assert_eq!(renderer.angles, vec![0.0]);
+    }
+
+    #[test]
+    fn rays_stop_at_walls_and_closed_doors() {
+        let (mut map, spawn) = Map::from_ascii(
+            "
+            #####
+            ##4##
+            #...#
+            #.D.#
+            #.P.#
+            #####
+            ",
+        )
+        .unwrap();
+        let player = player(spawn.unwrap());
+        let mut renderer = Renderer::new(settings(), vec![], vec![]);
+
+        // The door is drawn halfway through its cell.
+        renderer.calculate_ray(&map, &player, 0.0, 0);
+        assert_eq!(renderer.intersections.wall_type[0], 6);
+        assert!((renderer.intersections.distances[0] - 1.0).abs() < 1e-5);
+
+        map.doors.get_mut(&(2 + 3 * 5)).unwrap().offset = 0.0;
+        renderer.calculate_ray(&map, &player, 0.0, 0);
+        assert_eq!(renderer.intersections.wall_type[0], 4);
+        assert!((renderer.intersections.distances[0] - 2.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn each_kind_of_door_opens_its_own_way() {
+        let (mut map, spawn) = Map::from_ascii(
+            "
+            #####
+            ##4##
+            #...#
+            #.D.#
+            #.P.#
+            #####
+            ",
+        )
+        .unwrap();
+        let player = player(spawn.unwrap());
+        let mut renderer = Renderer::new(settings(), vec![], vec![]);
+        let door = 2 + 3 * 5;
+        let mut ray = |map: &mut Map, kind, offset| {
+            let door = map.doors.get_mut(&door).unwrap();
+            door.kind = kind;
+            door.offset = offset;
+            renderer.calculate_ray(map, &player, 0.0, 0);
+            (
+                renderer.intersections.wall_type[0],
+                renderer.intersections.lifts[0],
+            )
+        };
+
+        // The ray crosses the middle of the doorway, which a sliding panel leaves last.
+        assert_eq!(ray(&mut map, DoorKind::Slide, 0.6).0, 6);
+        assert_eq!(ray(&mut map, DoorKind::Slide, 0.4).0, 4);
+
+        // Rays pass under a rising door, and draw it in front of the wall behind.
+        let (wall_type, lift) = ray(&mut map, DoorKind::Lift, 0.6);
+        assert_eq!(wall_type, 4);
+        let lift = lift.unwrap();
+        assert_eq!((lift.cell, lift.offset), (door, 0.6));
+        assert!((lift.distance - 1.0).abs() < 1e-5);
+        assert_eq!(ray(&mut map, DoorKind::Lift, 1.0), (6, None));
+        assert_eq!(ray(&mut map, DoorKind::Lift, 0.0), (4, None));
+    }
+
+    #[test]
+    fn rising_door_is_cut_off_at_its_bottom_edge() {
+        let (mut map, spawn) = Map::from_ascii(
+            "
+            #####
+            ##4##
+            #...#
+            #.D.#
+            #.P.#
+            #####
+            ",
+        )
+        .unwrap();
+        let door = map.doors.get_mut(&(2 + 3 * 5)).unwrap();
+        door.kind = DoorKind::Lift;
+        door.offset = 0.5;
+        let lighting = Lighting::new(vec![2 + 4 * 5], &map.solid, map.size);
+
+        // The rising door's texture is red, every other texture is green.
+        let lift = DoorKind::Lift.texture();
+        let mut wall_textures = [0, 255, 0, 255].repeat(128 * 128 * (lift + 1));
+        wall_textures[128 * 128 * lift * 4..].copy_from_slice(&[255, 0, 0, 255].repeat(128 * 128));
+        let mut renderer = Renderer::new(settings(), wall_textures, vec![]);
+
+        renderer.render(
+            &map,
+            &player(spawn.unwrap()),
+            &mut [],
+            &lighting,
+            &Torch::with_intensity(1.0),
+        );
+
+        // A cell away the door fills the screen, so half of it covers the top half.
+        let frame = renderer.frame();
+        let pixel = |y: usize| &frame[(y * WIDTH + WIDTH / 2) * 4..][..4];
+        assert!((0..HEIGHT / 2 - 5).all(|y| pixel(y)[0] > 0 && pixel(y)[1] == 0));
+        assert!((HEIGHT / 2 + 5..HEIGHT).all(|y| pixel(y)[0] == 0));
+    }
+
+    #[test]
+    fn rays_hit_push_walls_between_cells() {
+        let (mut map, spawn) = Map::from_ascii(
+            "
+            #####
+            ##4##
+            #...#
+            #...#
+            #.S.#
+            #.P.#
+            #####
+            ",
+        )
+        .unwrap();
+        let player = player(spawn.unwrap());
+        let mut renderer = Renderer::new(settings(), vec![], vec![]);
+        assert!(map.push_wall(2 + 4 * 5, player.pos, &[]));
+
+        // Half a cell into its slide, the face of the wall is a cell away.
+        for _ in 0..60 {
+            map.update_push_walls();
+        }
+        renderer.calculate_ray(&map, &player, 0.0, 0);
+        assert_eq!(renderer.intersections.wall_type[0], 2);
+        assert_eq!(renderer.intersections.orientation[0], Orientation::S);
+        assert!((renderer.intersections.distances[0] - 1.0).abs() < 1e-3);
+
+        // Rays that pass beside the sliding wall go on to the walls of the room.
+        renderer.calculate_ray(&map, &player, 0.6, 0);
+        assert!(renderer.intersections.distances[0] > 1.5);
+    }
+}
@@ -0,0 +1,371 @@
+//! Saved games: the state of the world that changes while playing, on top of the level file
+//! it started from.
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use ggez::{filesystem, Context, GameError, GameResult};
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+
+use crate::level::Level;
+use crate::lighting::Torch;
+use crate::map::{Door, Key, PushWall};
+use crate::player::Player;
+use crate::renderer::RenderSettings;
+use crate::sprite::{Sprite, SpriteType};
+use crate::utilities::vector2::Vector2;
+use crate::GameRng;
+
+/// Save file written and read back by the quicksave and quickload keys, in the user data
+/// directory.
+pub const QUICKSAVE: &str = "/quicksave.ron";
+
+/// Everything needed to render the same frame again once the level file is loaded.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SaveGame {
+    /// Level file being played, e.g. `/level2.ron`.
+    pub level: PathBuf,
+    /// Game clock, which doors and animations are timed against.
+    pub time: f32,
+    /// The whole torch, whose flicker goes on from where it was.
+    pub torch: Torch,
+    /// Where the random numbers were at, so that a loaded game draws the same ones.
+    pub rng: RngState,
+    pub player: PlayerState,
+    pub solid: Vec<bool>,
+    pub doors: Vec<Door>,
+    #[serde(default)]
+    pub push_walls: Vec<PushWall>,
+    pub sprites: Vec<SpriteState>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PlayerState {
+    pub pos: (f32, f32),
+    /// Cells per second the player is moving at.
+    #[serde(default)]
+    pub velocity: (f32, f32),
+    pub dir: (f32, f32),
+    pub pitch: f32,
+    pub jump: f32,
+    pub height: f32,
+    #[serde(default)]
+    pub z: f32,
+    #[serde(default)]
+    pub vertical_velocity: f32,
+    pub walking: bool,
+    #[serde(default)]
+    pub keys: Vec<Key>,
+}
+
+/// Position of the game's random numbers in the stream drawn from its seed.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RngState {
+    pub seed: u64,
+    /// Words of 32 bits drawn so far.
+    pub drawn: u64,
+}
+
+impl RngState {
+    pub fn capture(seed: u64, rng: &GameRng) -> Self {
+        Self {
+            seed,
+            drawn: rng.get_word_pos() as u64,
+        }
+    }
+
+    /// A generator that draws the numbers `rng` would have drawn next when captured.
+    pub fn restore(&self) -> GameRng {
+        let mut rng = GameRng::seed_from_u64(self.seed);
+        rng.set_word_pos(self.drawn as u128);
+        rng
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SpriteState {
+    pub kind: SpriteType,
+    pub pos: (f32, f32),
+    pub time: f32,
+}
+
+impl SaveGame {
+    pub fn capture(
+        level_path: &Path,
+        level: &Level,
+        player: &Player,
+        time: f32,
+        torch: &Torch,
+        rng: RngState,
+    ) -> Self {
+        let mut doors: Vec<Door> = level.map.doors.values().cloned().collect();
+        doors.sort_by_key(|door| door.pos);
+        let mut push_walls: Vec<PushWall> = level.map.push_walls.values().cloned().collect();
+        push_walls.sort_by_key(|wall| wall.pos);
+        Self {
+            level: level_path.to_path_buf(),
+            time,
+            torch: torch.clone(),
+            rng,
+            player: PlayerState {
+                pos: (player.pos.x, player.pos.y),
+                velocity: (player.velocity.x, player.velocity.y),
+                dir: (player.dir_norm.x, player.dir_norm.y),
+                pitch: player.pitch,
+                jump: player.jump,
+                height: player.height,
+                z: player.z,
+                vertical_velocity: player.vertical_velocity,
+                keys: player.keys.iter().copied().collect(),
+                walking: player.walking,
+            },
+            solid: level.map.solid.clone(),
+            doors,
+            push_walls,
+            sprites: level
+                .sprites
+                .iter()
+                .map(|sprite| SpriteState {
+                    kind: SpriteType::ALL[sprite.stype],
+                    pos: (sprite.pos.x, sprite.pos.y),
+                    time: sprite.time(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Puts the saved state back into `level`, freshly loaded from [`SaveGame::level`], and
+    /// into `player`, whose projection is set for `settings`.
+    pub fn restore(
+        &self,
+        level: &mut Level,
+        player: &mut Player,
+        settings: &RenderSettings,
+    ) -> GameResult {
+        let map = &mut level.map;
+        if self.solid.len() != map.solid.len() {
+            return Err(load_error(format!(
+                "saved map has {} cells, expected {} for a {}x{} map",
+                self.solid.len(),
+                map.solid.len(),
+                map.size.0,
+                map.size.1
+            )));
+        }
+        if let Some(door) = self
+            .doors
+            .iter()
+            .find(|door| !map.doors.contains_key(&door.pos))
+        {
+            return Err(load_error(format!(
+                "door at ({}, {}) isn't in the level",
+                door.pos % map.size.0,
+                door.pos / map.size.0
+            )));
+        }
+        if let Some(wall) = self
+            .push_walls
+            .iter()
+            .find(|wall| !map.push_walls.contains_key(&wall.pos))
+        {
+            return Err(load_error(format!(
+                "push-wall at ({}, {}) isn't in the level",
+                wall.pos % map.size.0,
+                wall.pos / map.size.0
+            )));
+        }
+
+        for wall in &self.push_walls {
+            wall.place(map.size.0, &mut map.walls, &mut map.solid);
+            map.push_walls.insert(wall.pos, wall.clone());
+        }
+        map.solid.clone_from(&self.solid);
+        for door in &self.doors {
+            map.doors.insert(door.pos, door.clone());
+        }
+        level.sprites = self
+            .sprites
+            .iter()
+            .map(|state| {
+                let mut sprite = Sprite::new(state.kind, Vector2::new(state.pos.0, state.pos.1));
+                sprite.update(state.time);
+                sprite
+            })
+            .collect();
+
+        let state = &self.player;
+        player.pos = Vector2::new(state.pos.0, state.pos.1);
+        player.velocity = Vector2::new(state.velocity.0, state.velocity.1);
+        player.dir_norm = Vector2::new(state.dir.0, state.dir.1);
+        player.pitch = state.pitch;
+        player.jump = state.jump;
+        player.height = state.height;
+        player.z = state.z;
+        player.vertical_velocity = state.vertical_velocity;
+        player.keys = state.keys.iter().copied().collect();
+        player.walking = state.walking;
+        player.set_projection(settings);
+        Ok(())
+    }
+
+    /// Writes the save file through the ggez filesystem, in the user data directory.
+    pub fn save(&self, ctx: &mut Context, path: &Path) -> GameResult {
+        let text = self.to_ron()?;
+        filesystem::create(ctx, path)?.write_all(text.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn load(ctx: &mut Context, path: &Path) -> GameResult<Self> {
+        let mut text = String::new();
+        filesystem::open(ctx, path)?.read_to_string(&mut text)?;
+        Self::from_ron(&text)
+    }
+
+    pub fn to_ron(&self) -> GameResult<String> {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|e| GameError::CustomError(format!("save file: {}", e)))
+    }
+
+    pub fn from_ron(text: &str) -> GameResult<Self> {
+        ron::from_str(text).map_err(|e| load_error(e.to_string()))
+    }
+}
+
+fn load_error(message: impl std::fmt::Display) -> GameError {
+    GameError::ResourceLoadError(format!("save file: {}", message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::DoorState;
+    use crate::renderer::{read_rgba8, Renderer};
+    use crate::timestep::TICK;
+
+    fn render(level: &mut Level, player: &Player, torch: &Torch) -> Vec<u8> {
+        let lighting = level.lighting();
+        let mut renderer = Renderer::new(
+            settings(),
+            read_rgba8(Path::new("assets/wall128.png")).unwrap(),
+            read_rgba8(Path::new("assets/sprite128.png")).unwrap(),
+        );
+        renderer.render(&level.map, player, &mut level.sprites, &lighting, torch);
+        renderer.frame()
+    }
+
+    fn settings() -> RenderSettings {
+        RenderSettings::new(320, 180, 45.0, 2)
+    }
+
+    fn spawn(level: &Level) -> Player {
+        let dir = Vector2::new(level.spawn.dir.0, level.spawn.dir.1);
+        Player::new(
+            Vector2::new(level.spawn.pos.0, level.spawn.pos.1),
+            dir,
+            settings().plane(dir),
+            settings().planedist(),
+            0.0,
+            0.0,
+        )
+    }
+
+    fn rng(seed: u64) -> RngState {
+        RngState::capture(seed, &GameRng::seed_from_u64(seed))
+    }
+
+    #[test]
+    fn loaded_game_renders_the_saved_frame() {
+        let path = Path::new("assets/level1.ron");
+        let mut level = Level::load_file(path).unwrap();
+        let mut player = spawn(&level);
+        player.pos = Vector2::new(8.5, 8.5);
+        player.pitch = 20.0;
+        player.height = 0.4;
+        player.z = 0.05;
+        player.jump = 15.0;
+        let door = level.map.doors.get_mut(&(9 + 6 * 16)).unwrap();
+        door.offset = 0.4;
+        door.state = DoorState::Opening;
+        level
+            .sprites
+            .iter_mut()
+            .for_each(|sprite| sprite.update(2.7));
+        let torch = Torch::with_intensity(0.8);
+        let saved = render(&mut level, &player, &torch);
+
+        let text = SaveGame::capture(path, &level, &player, 12.0, &torch, rng(0))
+            .to_ron()
+            .unwrap();
+        let save = SaveGame::from_ron(&text).unwrap();
+        let mut loaded = Level::load_file(&save.level).unwrap();
+        let mut player = spawn(&loaded);
+        assert_ne!(
+            render(&mut loaded, &player, &Torch::with_intensity(1.0)),
+            saved
+        );
+        save.restore(&mut loaded, &mut player, &settings()).unwrap();
+
+        assert_eq!(save.time, 12.0);
+        assert!(render(&mut loaded, &player, &save.torch) == saved);
+    }
+
+    #[test]
+    fn rejects_saves_of_another_map() {
+        let mut level = Level::load_file(Path::new("assets/level1.ron")).unwrap();
+        let mut player = spawn(&level);
+        let torch = Torch::with_intensity(1.0);
+        let mut save = SaveGame::capture(
+            Path::new("/level1.ron"),
+            &level,
+            &player,
+            0.0,
+            &torch,
+            rng(0),
+        );
+        save.solid.pop();
+
+        match save.restore(&mut level, &mut player, &settings()) {
+            Err(GameError::ResourceLoadError(e)) => {
+                assert_eq!(
+                    e,
+                    "save file: saved map has 399 cells, expected 400 for a 16x25 map"
+                )
+            }
+            _ => panic!("save of another map was accepted"),
+        }
+    }
+
+    #[test]
+    fn loaded_game_flickers_like_the_saved_one() {
+        let level = Level::load_file(Path::new("assets/level1.ron")).unwrap();
+        let player = spawn(&level);
+        let mut rng = GameRng::seed_from_u64(5);
+        let mut torch = Torch::new(&mut rng);
+        let flicker = |torch: &mut Torch, rng: &mut GameRng, from: usize| {
+            (from..from + 600)
+                .map(|tick| {
+                    torch.update_intensity(tick as f32 * TICK, rng);
+                    torch.intensity
+                })
+                .collect::<Vec<_>>()
+        };
+        flicker(&mut torch, &mut rng, 0);
+
+        let captured = SaveGame::capture(
+            Path::new("/level1.ron"),
+            &level,
+            &player,
+            10.0,
+            &torch,
+            RngState::capture(5, &rng),
+        );
+        let save = SaveGame::from_ron(&captured.to_ron().unwrap()).unwrap();
+        let mut loaded_torch = save.torch;
+        let mut loaded_rng = save.rng.restore();
+
+        assert_eq!(
+            flicker(&mut loaded_torch, &mut loaded_rng, 600),
+            flicker(&mut torch, &mut rng, 600)
+        );
+    }
+}
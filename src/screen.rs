@@ -41,6 +41,14 @@ impl Screen {
         self.wall_textures = wall_textures;
         self.sprite_textures = sprite_textures;
     }
+    /// Reallocates the image for a new resolution, keeping the textures.
+    pub fn resize(&mut self, widthf: f32, heightf: f32) {
+        self.width = widthf as usize;
+        self.height = heightf as usize;
+        self.reset_img();
+        self.img_arr_len = self.img_arr.len();
+    }
+
     #[allow(dead_code)]
     pub fn reset_img(&mut self) {
         self.img_arr = vec![0; (self.width * self.height) * 4];
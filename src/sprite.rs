@@ -1,9 +1,6 @@
-use ggez::{
-    graphics::{self},
-    Context,
-};
+use serde::{Deserialize, Serialize};
 
-use crate::{player::Player, screen::Screen, utilities::vector2::Vector2};
+use crate::{map::Key, player::Player, screen::Screen, utilities::vector2::Vector2};
 const PI: f32 = std::f32::consts::PI;
 const TEX_SIZE: usize = 128;
 pub struct Sprite {
@@ -37,8 +34,28 @@ impl Sprite {
         self.time = time;
     }
 
-    pub fn set_drawing_bounds(&mut self, ctx: &mut Context, player: &Player, rays_per_pixel: f32) {
-        let (w, h) = graphics::drawable_size(ctx);
+    /// Whether the player bumps into the sprite instead of walking through it.
+    pub fn solid(&self) -> bool {
+        SpriteType::ALL[self.stype].solid()
+    }
+
+    /// Key the player picks up by walking over the sprite, if it is one.
+    pub fn key(&self) -> Option<Key> {
+        SpriteType::ALL[self.stype].key()
+    }
+
+    /// Time the animation was last updated to.
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn set_drawing_bounds(
+        &mut self,
+        player: &Player,
+        screen_size: (f32, f32),
+        rays_per_pixel: f32,
+    ) {
+        let (w, h) = screen_size;
         let sprite_delpos = self.pos - player.pos;
         let inv_det =
             1.0 / (player.plane.x * player.dir_norm.y - player.dir_norm.x * player.plane.y);
@@ -137,7 +154,7 @@ impl Sprite {
                 sprite_rotation = 0;
             }
 
-            let stx = ((stripe - (-self.bounds.size as f32 * 0.5 + self.bounds.sprite_screen_x))
+            let stx = ((stripe - (-self.bounds.size * 0.5 + self.bounds.sprite_screen_x))
                 * TEX_SIZE as f32
                 / self.bounds.size) as usize;
             if distance * distance / (cos * cos) > self.distance2 {
@@ -196,11 +213,43 @@ impl Default for Bounds {
         Self::new(0.0, 0.0, 0.0, 0.0, 0.0, vec![], 0.0)
     }
 }
-#[allow(dead_code)] 
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum SpriteType {
     Armor = 0,
     CandleHolder = 1,
     Bat = 2,
     Torch = 3,
     Gore = 4,
+    RedKey = 5,
+    BlueKey = 6,
+    YellowKey = 7,
+}
+
+impl SpriteType {
+    /// Every kind, indexed by its value.
+    pub const ALL: [SpriteType; 8] = [
+        SpriteType::Armor,
+        SpriteType::CandleHolder,
+        SpriteType::Bat,
+        SpriteType::Torch,
+        SpriteType::Gore,
+        SpriteType::RedKey,
+        SpriteType::BlueKey,
+        SpriteType::YellowKey,
+    ];
+
+    /// Whether sprites of this kind stand on the floor in the player's way.
+    pub fn solid(self) -> bool {
+        matches!(self, SpriteType::Armor | SpriteType::CandleHolder)
+    }
+
+    pub fn key(self) -> Option<Key> {
+        match self {
+            SpriteType::RedKey => Some(Key::Red),
+            SpriteType::BlueKey => Some(Key::Blue),
+            SpriteType::YellowKey => Some(Key::Yellow),
+            _ => None,
+        }
+    }
 }
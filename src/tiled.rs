@@ -0,0 +1,476 @@
+//! Imports levels made with the [Tiled](https://www.mapeditor.org) map editor, saved either as
+//! XML (`.tmx`) or JSON (`.tmj`).
+//!
+//! The map uses `wall128.png` as its tileset, so that the tile picked in Tiled is the texture
+//! drawn in game. It is made of:
+//!
+//! - a `walls` tile layer, where the door tile makes a door and every other tile a wall;
+//! - optional `floors` and `ceilings` tile layers, empty cells using the first texture;
+//! - object layers whose objects have one of these types, set in the object's class field:
+//!   - `spawn`: where the player starts, facing the direction of the object's rotation;
+//!   - `light`: a light source in the cell it is in;
+//!   - `door`: a door in the cell it is in, locked with the key named by the object's name if it
+//!     has one. The custom properties `kind` (`Split`, `Slide` or `Lift`), `speed` and `hold`
+//!     set how it opens, as in a level file;
+//!   - `exit`: an exit in the cell it is in, to the level file named by the object's name, or
+//!     to the next level of the campaign when it has none;
+//!   - `sprite`: a sprite whose kind is the object's name, e.g. `Armor`.
+//!
+//! Tile layers must be saved with the CSV layer format.
+use serde::Deserialize;
+use serde_json::Value;
+
+use ggez::{GameError, GameResult};
+
+use crate::level::{
+    DoorPlacement, ExitPlacement, Level, LevelDescription, SpawnPoint, SpritePlacement,
+};
+use crate::map::{DoorKind, Key, Map, DOOR_HOLD, DOOR_SPEED};
+use crate::palette::{Palette, Tile};
+use crate::sprite::SpriteType;
+
+/// Bits of a tile id that flip or rotate the tile, which walls don't care about.
+const FLIP_FLAGS: u32 = 0xf000_0000;
+/// Texture of the door tile.
+const DOOR: usize = 6;
+
+/// The parts of a Tiled map that make a level, whatever the format it was saved in.
+#[derive(Debug, Default, Deserialize)]
+struct TiledMap {
+    width: usize,
+    height: usize,
+    tilewidth: f32,
+    tileheight: f32,
+    #[serde(default)]
+    tilesets: Vec<Tileset>,
+    #[serde(default)]
+    layers: Vec<Layer>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Tileset {
+    firstgid: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Layer {
+    Tilelayer {
+        name: String,
+        /// Missing when the tile ids are saved as a JSON array.
+        encoding: Option<String>,
+        #[serde(default)]
+        data: Value,
+    },
+    Objectgroup {
+        objects: Vec<Object>,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Object {
+    id: u32,
+    name: String,
+    #[serde(rename = "type", alias = "class")]
+    kind: String,
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+    rotation: f32,
+    gid: Option<u32>,
+    /// Custom properties set on the object.
+    properties: Vec<Property>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Property {
+    name: String,
+    value: Value,
+}
+
+impl Object {
+    /// Custom property `name` read by `parse`, `None` if the object doesn't have it.
+    fn property<T>(
+        &self,
+        name: &str,
+        parse: impl FnOnce(&str) -> Option<T>,
+    ) -> GameResult<Option<T>> {
+        let value = match self
+            .properties
+            .iter()
+            .find(|property| property.name == name)
+        {
+            Some(property) => match &property.value {
+                Value::String(text) => text.clone(),
+                value => value.to_string(),
+            },
+            None => return Ok(None),
+        };
+        parse(&value).map(Some).ok_or_else(|| {
+            load_error(format!(
+                "object {} has an invalid {} '{}'",
+                self.id, name, value
+            ))
+        })
+    }
+}
+
+/// Imports a level from a Tiled map saved as XML.
+pub fn from_tmx(text: &str) -> GameResult<Level> {
+    let document = roxmltree::Document::parse(text).map_err(|e| load_error(e.to_string()))?;
+    let root = document.root_element();
+    let mut map = TiledMap {
+        width: attribute(root, "width")?,
+        height: attribute(root, "height")?,
+        tilewidth: attribute(root, "tilewidth")?,
+        tileheight: attribute(root, "tileheight")?,
+        ..Default::default()
+    };
+
+    for node in root.children().filter(|node| node.is_element()) {
+        match node.tag_name().name() {
+            "tileset" => map.tilesets.push(Tileset {
+                firstgid: attribute(node, "firstgid")?,
+            }),
+            "layer" => {
+                let data = node
+                    .children()
+                    .find(|child| child.has_tag_name("data"))
+                    .ok_or_else(|| load_error("tile layer without data"))?;
+                map.layers.push(Layer::Tilelayer {
+                    name: node.attribute("name").unwrap_or("").to_string(),
+                    encoding: data.attribute("encoding").map(str::to_string),
+                    data: Value::String(data.text().unwrap_or("").to_string()),
+                });
+            }
+            "objectgroup" => {
+                let objects = node
+                    .children()
+                    .filter(|child| child.has_tag_name("object"))
+                    .map(|object| {
+                        Ok(Object {
+                            id: attribute(object, "id")?,
+                            name: object.attribute("name").unwrap_or("").to_string(),
+                            kind: object
+                                .attribute("type")
+                                .or_else(|| object.attribute("class"))
+                                .unwrap_or("")
+                                .to_string(),
+                            x: attribute(object, "x")?,
+                            y: attribute(object, "y")?,
+                            width: attribute(object, "width").unwrap_or(0.0),
+                            height: attribute(object, "height").unwrap_or(0.0),
+                            rotation: attribute(object, "rotation").unwrap_or(0.0),
+                            gid: attribute(object, "gid").ok(),
+                            properties: object
+                                .children()
+                                .filter(|child| child.has_tag_name("properties"))
+                                .flat_map(|properties| properties.children())
+                                .filter(|child| child.has_tag_name("property"))
+                                .map(|property| {
+                                    Ok(Property {
+                                        name: attribute(property, "name")?,
+                                        value: Value::String(
+                                            property.attribute("value").unwrap_or("").to_string(),
+                                        ),
+                                    })
+                                })
+                                .collect::<GameResult<_>>()?,
+                        })
+                    })
+                    .collect::<GameResult<_>>()?;
+                map.layers.push(Layer::Objectgroup { objects });
+            }
+            _ => (),
+        }
+    }
+    map.into_level()
+}
+
+/// Imports a level from a Tiled map saved as JSON.
+pub fn from_tmj(text: &str) -> GameResult<Level> {
+    let map: TiledMap = serde_json::from_str(text).map_err(|e| load_error(e.to_string()))?;
+    map.into_level()
+}
+
+fn attribute<T: std::str::FromStr>(node: roxmltree::Node, name: &str) -> GameResult<T> {
+    let value = node.attribute(name).ok_or_else(|| {
+        load_error(format!(
+            "<{}> has no {} attribute",
+            node.tag_name().name(),
+            name
+        ))
+    })?;
+    value.parse().map_err(|_| {
+        load_error(format!(
+            "invalid {} '{}' in <{}>",
+            name,
+            value,
+            node.tag_name().name()
+        ))
+    })
+}
+
+fn load_error(message: impl std::fmt::Display) -> GameError {
+    GameError::ResourceLoadError(format!("Tiled map: {}", message))
+}
+
+impl TiledMap {
+    fn into_level(self) -> GameResult<Level> {
+        let size = (self.width, self.height);
+        let walls = self
+            .tile_layer("walls")?
+            .ok_or_else(|| load_error("missing walls tile layer"))?;
+        let tiles = walls
+            .iter()
+            .enumerate()
+            .map(|(i, texture)| match texture {
+                None => Ok(Tile::Empty),
+                Some(DOOR) => Ok(Tile::Door),
+                Some(0) => Err(load_error(format!(
+                    "wall at ({}, {}) uses the first tile, which is reserved for empty cells",
+                    i % self.width,
+                    i / self.width
+                ))),
+                Some(texture) => Ok(Tile::Wall(*texture)),
+            })
+            .collect::<GameResult<Vec<_>>>()?;
+        let textures = |layer: Option<Vec<Option<usize>>>| match layer {
+            Some(layer) => layer.iter().map(|texture| texture.unwrap_or(0)).collect(),
+            None => vec![0; self.width * self.height],
+        };
+        let floors = textures(self.tile_layer("floors")?);
+        let ceilings = textures(self.tile_layer("ceilings")?);
+        let map = Map::from_tiles(&tiles, floors, ceilings, size)?;
+
+        let mut spawn = None;
+        let mut sprites = Vec::new();
+        let mut lights = Vec::new();
+        let mut doors = Vec::new();
+        let mut exits = Vec::new();
+        for object in self.layers.iter().flat_map(|layer| match layer {
+            Layer::Objectgroup { objects } => objects.as_slice(),
+            _ => &[],
+        }) {
+            let pos = self.position(object);
+            let cell = (pos.0.max(0.0) as usize, pos.1.max(0.0) as usize);
+            match object.kind.as_str() {
+                "spawn" if spawn.is_some() => {
+                    return Err(load_error(format!(
+                        "object {} is a second spawn",
+                        object.id
+                    )))
+                }
+                "spawn" => {
+                    let angle = object.rotation.to_radians();
+                    spawn = Some(SpawnPoint {
+                        pos,
+                        dir: (angle.sin(), -angle.cos()),
+                    });
+                }
+                "light" => lights.push(cell),
+                "door" => doors.push(DoorPlacement {
+                    pos: cell,
+                    speed: object
+                        .property("speed", |speed| speed.parse().ok())?
+                        .unwrap_or(DOOR_SPEED),
+                    hold: Some(
+                        object
+                            .property("hold", |hold| hold.parse().ok())?
+                            .unwrap_or(DOOR_HOLD),
+                    ),
+                    kind: object
+                        .property("kind", |kind| ron::from_str::<DoorKind>(kind).ok())?
+                        .unwrap_or_default(),
+                    lock: match object.name.as_str() {
+                        "" => None,
+                        name => Some(ron::from_str::<Key>(name).map_err(|_| {
+                            load_error(format!(
+                                "object {} is locked with an unknown key '{}'",
+                                object.id, name
+                            ))
+                        })?),
+                    },
+                }),
+                "exit" => exits.push(ExitPlacement {
+                    pos: cell,
+                    to: Some(object.name.clone()).filter(|name| !name.is_empty()),
+                }),
+                "sprite" => sprites.push(SpritePlacement {
+                    kind: ron::from_str::<SpriteType>(&object.name).map_err(|_| {
+                        load_error(format!(
+                            "object {} is an unknown sprite '{}'",
+                            object.id, object.name
+                        ))
+                    })?,
+                    pos,
+                }),
+                kind => {
+                    return Err(load_error(format!(
+                        "object {} has unknown type '{}'",
+                        object.id, kind
+                    )))
+                }
+            }
+        }
+
+        let description = LevelDescription {
+            walls: "walls".to_string(),
+            floors: "floors".to_string(),
+            spawn: spawn.ok_or_else(|| load_error("missing spawn object"))?,
+            sprites,
+            lights,
+            doors,
+            exits,
+            surfaces: Default::default(),
+            palette: Palette::default(),
+        };
+        Level::new(description, map)
+    }
+
+    /// Textures of a tile layer, `None` where no tile is painted.
+    fn tile_layer(&self, name: &str) -> GameResult<Option<Vec<Option<usize>>>> {
+        let (encoding, data) = match self.layers.iter().find_map(|layer| match layer {
+            Layer::Tilelayer {
+                name: n,
+                encoding,
+                data,
+            } if n == name => Some((encoding.as_deref(), data)),
+            _ => None,
+        }) {
+            Some(layer) => layer,
+            None => return Ok(None),
+        };
+
+        let ids: Vec<u32> = match (encoding, data) {
+            (None, Value::Array(ids)) => ids
+                .iter()
+                .map(|id| id.as_u64().map(|id| id as u32))
+                .collect::<Option<_>>()
+                .ok_or_else(|| load_error(format!("{} layer holds invalid tile ids", name)))?,
+            (Some("csv"), Value::String(csv)) => csv
+                .split(',')
+                .map(|id| id.trim().parse())
+                .collect::<Result<_, _>>()
+                .map_err(|_| load_error(format!("{} layer holds invalid tile ids", name)))?,
+            _ => {
+                return Err(load_error(format!(
+                    "{} layer isn't saved as CSV, change its tile layer format",
+                    name
+                )))
+            }
+        };
+        if ids.len() != self.width * self.height {
+            return Err(load_error(format!(
+                "{} layer has {} tiles, expected {} for a {}x{} map",
+                name,
+                ids.len(),
+                self.width * self.height,
+                self.width,
+                self.height
+            )));
+        }
+
+        let firstgid = self.tilesets.first().map_or(1, |tileset| tileset.firstgid);
+        Ok(Some(
+            ids.iter()
+                .map(|id| match id & !FLIP_FLAGS {
+                    0 => None,
+                    gid => Some(gid.saturating_sub(firstgid) as usize),
+                })
+                .collect(),
+        ))
+    }
+
+    /// Position of an object in map cells. Rectangles and tiles count from their centre.
+    fn position(&self, object: &Object) -> (f32, f32) {
+        // Tile objects are anchored at their bottom left corner, the others at the top left.
+        let top = match object.gid {
+            Some(_) => object.y - object.height,
+            None => object.y,
+        };
+        (
+            (object.x + object.width * 0.5) / self.tilewidth,
+            (top + object.height * 0.5) / self.tileheight,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::utilities::vector2::Vector2;
+
+    fn fixture(name: &str) -> String {
+        std::fs::read_to_string(Path::new("tests/fixtures").join(name)).unwrap()
+    }
+
+    #[test]
+    fn tmx_and_tmj_match_png_level() {
+        let mut png = Map::load_file(
+            Path::new("tests/fixtures/walls.png"),
+            Path::new("tests/fixtures/floors.png"),
+            &Palette::default(),
+        )
+        .unwrap();
+        png.add_door(7 + 7 * 10);
+
+        for level in [
+            from_tmx(&fixture("level.tmx")).unwrap(),
+            from_tmj(&fixture("level.tmj")).unwrap(),
+        ] {
+            assert_eq!(level.map.size, png.size);
+            assert_eq!(level.map.walls, png.walls);
+            assert_eq!(level.map.floors, png.floors);
+            assert_eq!(level.map.solid, png.solid);
+            assert_eq!(level.map.ceilings[1 + 10], 1);
+            assert_eq!(level.map.doors.len(), 2);
+            let door = &level.map.doors[&(7 + 7 * 10)];
+            assert_eq!(door.kind, DoorKind::Lift);
+            assert_eq!((door.speed, door.hold), (0.5, Some(2.5)));
+            let door = &level.map.doors[&(4 + 5 * 10)];
+            assert_eq!(door.kind, DoorKind::Split);
+            assert_eq!((door.speed, door.hold), (DOOR_SPEED, Some(DOOR_HOLD)));
+
+            assert_eq!(level.spawn.pos, (5.5, 3.5));
+            assert!((level.spawn.dir.0 - 0.0).abs() < 1e-6);
+            assert!((level.spawn.dir.1 + 1.0).abs() < 1e-6);
+            assert_eq!(level.lights, vec![1 + 10, 8 + 10 * 7]);
+            assert_eq!(level.sprites.len(), 3);
+            assert_eq!(level.sprites[0].pos, Vector2::new(2.5, 3.5));
+            assert_eq!(level.sprites[1].stype, SpriteType::Bat as usize);
+            assert_eq!(level.exits[0].cell, 1 + 10 * 8);
+            assert_eq!(level.exits[0].to.as_deref(), Some("level2.ron"));
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_objects() {
+        let text = fixture("level.tmj").replace("\"Bat\"", "\"Dragon\"");
+
+        match from_tmj(&text) {
+            Err(GameError::ResourceLoadError(e)) => {
+                assert_eq!(e, "Tiled map: object 4 is an unknown sprite 'Dragon'")
+            }
+            _ => panic!("unknown sprite was accepted"),
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_door_kinds() {
+        let text = fixture("level.tmj").replace("\"Lift\"", "\"Trapdoor\"");
+
+        match from_tmj(&text) {
+            Err(GameError::ResourceLoadError(e)) => {
+                assert_eq!(e, "Tiled map: object 7 has an invalid kind 'Trapdoor'")
+            }
+            _ => panic!("unknown door kind was accepted"),
+        }
+    }
+}
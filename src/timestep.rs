@@ -0,0 +1,84 @@
+//! Fixed timestep: the game is simulated in ticks of the same length whatever the frame rate,
+//! and drawn in between the last two ticks.
+
+/// Seconds simulated by one tick. Speeds are given per second and taken a tick at a time.
+pub const TICK: f32 = 1.0 / 60.0;
+/// Longest frame simulated, so that a hitch such as loading a level doesn't take many ticks to
+/// catch up with.
+const MAX_FRAME_TIME: f32 = 0.25;
+
+/// Splits the time between frames into ticks.
+#[derive(Debug, Default)]
+pub struct Timestep {
+    /// Time left over after the last tick.
+    accumulator: f32,
+}
+
+impl Timestep {
+    /// Adds the time a frame took and returns how many ticks to simulate.
+    pub fn advance(&mut self, dt: f32) -> usize {
+        self.accumulator += dt.min(MAX_FRAME_TIME);
+        let mut ticks = 0;
+        while self.accumulator >= TICK {
+            self.accumulator -= TICK;
+            ticks += 1;
+        }
+        ticks
+    }
+
+    /// How far the frame is between the last tick and the next one, from 0 to 1.
+    pub fn alpha(&self) -> f32 {
+        self.accumulator / TICK
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{Door, DoorState};
+
+    /// Seconds it takes a door to open when the game runs at `fps`.
+    fn door_opening_time(fps: f32) -> f32 {
+        let mut timestep = Timestep::default();
+        let mut solid = vec![true];
+        let mut door = Door::new(0);
+        door.toggle();
+        let (mut time, mut frames) = (0.0, 0);
+        while door.state != DoorState::Open {
+            frames += 1;
+            for _ in 0..timestep.advance(1.0 / fps) {
+                time += TICK;
+                door.update(time, false, &mut solid);
+            }
+        }
+        assert!(!solid[0]);
+        frames as f32 / fps
+    }
+
+    #[test]
+    fn simulates_the_same_time_at_any_frame_rate() {
+        for fps in [30.0, 60.0, 75.0, 144.0, 240.0] {
+            let mut timestep = Timestep::default();
+            let ticks: usize = (0..fps as usize).map(|_| timestep.advance(1.0 / fps)).sum();
+
+            assert!((59..=60).contains(&ticks), "{} ticks at {} FPS", ticks, fps);
+            assert!((0.0..1.0).contains(&timestep.alpha()));
+        }
+    }
+
+    #[test]
+    fn doors_open_as_fast_at_any_frame_rate() {
+        let expected = door_opening_time(60.0);
+
+        for fps in [30.0, 120.0, 144.0] {
+            assert!((door_opening_time(fps) - expected).abs() <= 2.0 * TICK);
+        }
+    }
+
+    #[test]
+    fn skips_time_lost_in_a_hitch() {
+        let mut timestep = Timestep::default();
+
+        assert!((14..=15).contains(&timestep.advance(3.0)));
+    }
+}
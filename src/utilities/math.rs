@@ -3,7 +3,6 @@ use core::ops::Div;
 use core::ops::Sub;
 use std::fmt::Debug;
 #[allow(dead_code)]
-#[allow(dead_code)]
 pub fn linspace<T>(x0: T, xend: T, n: u16) -> Vec<T>
 where
     T: Sub<Output = T> + Add<Output = T> + Div<Output = T> + Clone + Debug,
@@ -13,9 +12,7 @@ where
     let segments: T = (n - 1)
         .try_into()
         .expect("requested number of elements did not fit into T");
-    let n_size: usize = n
-        .try_into()
-        .expect("requested number of elements exceeds usize");
+    let n_size: usize = n.into();
 
     let dx = (xend - x0.clone()) / segments;
 
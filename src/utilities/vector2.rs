@@ -191,7 +191,7 @@ mod tests {
 
     fn try_rotation() {
         let v1 = Vector2::new(1.0f32, 1.0);
-        let theta = 45.0 * 3.14159265358979 / 180.0;
+        let theta = 45.0 * std::f32::consts::PI / 180.0;
         let res = Vector2::new(0.0f32, 2.0.sqrt());
         assert_eq!(Vector2::rotate(v1, theta), res);
     }
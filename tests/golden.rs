@@ -0,0 +1,183 @@
+//! Golden-image regression tests for the software renderer.
+//!
+//! Every test renders a fixed camera pose in the fixture level under `tests/fixtures` and
+//! compares the frame against the reference image in `tests/golden`. When a frame doesn't
+//! match, a diff image highlighting the offending pixels in red is written next to the test
+//! binaries and its path is reported. Run with `UPDATE_GOLDEN=1` to regenerate the references
+//! after an intended change to the renderer.
+use std::path::{Path, PathBuf};
+
+use raycaster::lighting::{Lighting, Torch};
+use raycaster::map::Map;
+use raycaster::palette::Palette;
+use raycaster::player::Player;
+use raycaster::renderer::{read_rgba8, write_frame, RenderSettings, Renderer};
+use raycaster::sprite::{Sprite, SpriteType};
+use raycaster::utilities::vector2::Vector2;
+
+const WIDTH: usize = 320;
+const HEIGHT: usize = 180;
+/// Largest difference allowed in any channel before a pixel counts as different.
+const CHANNEL_TOLERANCE: u8 = 8;
+/// Fraction of pixels that may differ, to absorb rounding at texture and wall edges.
+const PIXEL_TOLERANCE: f32 = 0.001;
+
+struct Fixture {
+    map: Map,
+    lighting: Lighting,
+    sprites: Vec<Sprite>,
+}
+
+impl Fixture {
+    fn new() -> Self {
+        let map = Map::load_file(
+            Path::new("tests/fixtures/walls.png"),
+            Path::new("tests/fixtures/floors.png"),
+            &Palette::default(),
+        )
+        .unwrap();
+        let lighting = Lighting::new(vec![1 + 10, 8 + 10 * 7], &map.solid, map.size);
+        let sprites = vec![
+            Sprite::new(SpriteType::Armor, Vector2::new(2.5, 3.5)),
+            Sprite::new(SpriteType::Bat, Vector2::new(7.5, 7.5)),
+            Sprite::new(SpriteType::Torch, Vector2::new(1.5, 1.048)),
+        ];
+        Self {
+            map,
+            lighting,
+            sprites,
+        }
+    }
+
+    fn render(&mut self, pos: [f32; 2], dir: [f32; 2], pitch: f32, jump: f32) -> Vec<u8> {
+        let settings = RenderSettings::new(WIDTH, HEIGHT, 45.0, 2);
+        let mut dir_norm = Vector2::new(dir[0], dir[1]);
+        dir_norm.normalize();
+        let player = Player::new(
+            Vector2::new(pos[0], pos[1]),
+            dir_norm,
+            settings.plane(dir_norm),
+            settings.planedist(),
+            pitch,
+            jump,
+        );
+        let mut renderer = Renderer::new(
+            settings,
+            read_rgba8(Path::new("assets/wall128.png")).unwrap(),
+            read_rgba8(Path::new("assets/sprite128.png")).unwrap(),
+        );
+        renderer.render(
+            &self.map,
+            &player,
+            &mut self.sprites,
+            &self.lighting,
+            &Torch::with_intensity(1.0),
+        );
+        renderer.frame()
+    }
+
+    fn door(&mut self, pos: (usize, usize), offset: f32) {
+        let cell = pos.0 + pos.1 * self.map.size.0;
+        let door = self.map.doors.get_mut(&cell).unwrap();
+        door.offset = offset;
+    }
+}
+
+fn assert_golden(name: &str, frame: Vec<u8>) {
+    let reference = Path::new("tests/golden").join(format!("{}.png", name));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        write_frame(&reference, frame, WIDTH, HEIGHT).unwrap();
+        return;
+    }
+
+    let expected = read_rgba8(&reference).unwrap_or_else(|e| {
+        panic!(
+            "can't read {}: {}, run with UPDATE_GOLDEN=1 to create it",
+            reference.display(),
+            e
+        )
+    });
+    assert_eq!(expected.len(), frame.len(), "{} has the wrong size", name);
+
+    let mut diff = Vec::with_capacity(frame.len());
+    let mut mismatches = 0;
+    for (actual, expected) in frame.chunks(4).zip(expected.chunks(4)) {
+        if actual
+            .iter()
+            .zip(expected)
+            .any(|(a, e)| a.abs_diff(*e) > CHANNEL_TOLERANCE)
+        {
+            mismatches += 1;
+            diff.extend_from_slice(&[255, 0, 0, 255]);
+        } else {
+            let grey = (actual[0] / 3 + actual[1] / 3 + actual[2] / 3) / 2;
+            diff.extend_from_slice(&[grey, grey, grey, 255]);
+        }
+    }
+
+    if mismatches as f32 > PIXEL_TOLERANCE * (WIDTH * HEIGHT) as f32 {
+        let diff_path =
+            PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.diff.png", name));
+        write_frame(&diff_path, diff, WIDTH, HEIGHT).unwrap();
+        panic!(
+            "{} differs from its reference in {} pixels, diff written to {}",
+            name,
+            mismatches,
+            diff_path.display()
+        );
+    }
+}
+
+#[test]
+fn facing_wall() {
+    let frame = Fixture::new().render([5.5, 3.5], [0.0, -1.0], 0.0, 0.0);
+    assert_golden("facing_wall", frame);
+}
+
+#[test]
+fn looking_up_and_crouching() {
+    let frame = Fixture::new().render([5.5, 3.5], [1.0, -0.5], 150.0, -200.0);
+    assert_golden("looking_up_and_crouching", frame);
+}
+
+#[test]
+fn door_closed() {
+    let frame = Fixture::new().render([4.5, 7.5], [0.0, -1.0], 0.0, 0.0);
+    assert_golden("door_closed", frame);
+}
+
+#[test]
+fn door_half_open() {
+    let mut fixture = Fixture::new();
+    fixture.door((4, 5), 0.5);
+    let frame = fixture.render([4.5, 7.5], [0.0, -1.0], 0.0, 0.0);
+    assert_golden("door_half_open", frame);
+}
+
+#[test]
+fn door_side_walls() {
+    let mut fixture = Fixture::new();
+    fixture.door((4, 5), 0.2);
+    let frame = fixture.render([4.3, 7.5], [0.35, -1.0], 0.0, 0.0);
+    assert_golden("door_side_walls", frame);
+}
+
+#[test]
+fn sprite_clipped_at_frame_edges() {
+    let frame = Fixture::new().render([2.3, 4.1], [0.3, -1.0], 120.0, 60.0);
+    assert_golden("sprite_clipped_at_frame_edges", frame);
+}
+
+#[test]
+fn smooth_lighting_near_torch() {
+    let frame = Fixture::new().render([2.5, 2.5], [-1.0, -1.0], 0.0, 0.0);
+    assert_golden("smooth_lighting_near_torch", frame);
+}
+
+#[test]
+fn flat_lighting_near_torch() {
+    let mut fixture = Fixture::new();
+    fixture.lighting.smooth_switch = false;
+    let frame = fixture.render([2.5, 2.5], [-1.0, -1.0], 0.0, 0.0);
+    assert_golden("flat_lighting_near_torch", frame);
+}